@@ -3,6 +3,7 @@ use crate::vec3::{Vec3, Vec3f64};
 use crate::{interval::Interval, vec3::Point};
 use std::ops::Add;
 
+#[allow(clippy::upper_case_acronyms)]
 pub type AABB = Vec3<Interval>;
 
 impl AABB {
@@ -49,11 +50,11 @@ impl AABB {
 
     pub fn hit(&self, r: &Ray, mut ray_t: Interval) -> bool {
         let ray_orig = r.origin();
-        let ray_dir = r.direction();
+        let ray_inv_dir = r.inv_direction();
 
         for axis in 0..3 {
             let ax = &self[axis];
-            let adinv = 1.0 / ray_dir[axis];
+            let adinv = ray_inv_dir[axis];
 
             let t0 = (ax.min - ray_orig[axis]) * adinv;
             let t1 = (ax.max - ray_orig[axis]) * adinv;
@@ -100,6 +101,19 @@ impl AABB {
         }
     }
 
+    pub fn centroid(&self) -> Point {
+        Point::new(
+            self[0].min + self[0].max,
+            self[1].min + self[1].max,
+            self[2].min + self[2].max,
+        ) * 0.5
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let (x, y, z) = (self[0].size(), self[1].size(), self[2].size());
+        2.0 * (x * y + y * z + z * x)
+    }
+
     pub fn rotate<const AXIS: usize>(&self, radians: f64) -> Self {
         let (sin_theta, cos_theta) = radians.sin_cos();
 
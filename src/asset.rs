@@ -0,0 +1,217 @@
+use crate::rtwimage::RtwImage;
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+// Number of parent directories whose images/ or models/ subdirectory is searched.
+const PARENT_LEVELS: usize = 6;
+
+#[derive(Clone, Copy)]
+pub enum AssetKind {
+    Image,
+    Model, // Model and scene files
+}
+
+impl AssetKind {
+    fn env_var(self) -> &'static str {
+        match self {
+            Self::Image => "RTW_IMAGES",
+            Self::Model => "RTW_MODELS",
+        }
+    }
+
+    fn subdir(self) -> &'static str {
+        match self {
+            Self::Image => "images",
+            Self::Model => "models",
+        }
+    }
+}
+
+/// Locates image, model and scene files, and keeps every image it decoded.
+///
+/// A file is looked up, in order:
+/// - next to the file referencing it, e.g. the MTL textures of an OBJ model
+/// - in the search directories added with `with_search_dir`, in the order they were added
+/// - in the directory named by the RTW_IMAGES or RTW_MODELS environment variable
+/// - as given, relative to the current directory
+/// - in the images/ or models/ subdirectory of the current directory and of up to six parent
+///   directories
+///
+/// Images are cached by path, so a texture used by many materials is only decoded once. Models
+/// are kept across runs in the cache directory, if one is set.
+#[derive(Default)]
+pub struct AssetResolver {
+    search_dirs: Vec<PathBuf>,
+    cache_dir: Option<PathBuf>, // Directory of the model cache files
+    images: Mutex<HashMap<PathBuf, Arc<RtwImage>>>,
+}
+
+/// Failure to load an image, model or scene file.
+#[derive(Debug)]
+pub enum AssetError {
+    // No file of that name in any of the searched locations
+    NotFound {
+        name: String,
+        searched: Vec<PathBuf>,
+    },
+    // The file exists but could not be read or decoded
+    Parse {
+        path: PathBuf,
+        message: String,
+    },
+    // The model file was read but has no triangles
+    EmptyMesh {
+        path: PathBuf,
+    },
+    // The scene file was read but has no objects to render
+    EmptyScene {
+        path: PathBuf,
+    },
+    // A texture referenced by a model or scene file could not be found
+    MissingTexture {
+        model: PathBuf,
+        texture: String,
+        searched: Vec<PathBuf>,
+    },
+}
+
+impl AssetError {
+    pub fn parse(path: impl Into<PathBuf>, message: impl Display) -> Self {
+        Self::Parse {
+            path: path.into(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn for_texture_of(self, model: impl Into<PathBuf>) -> Self {
+        // Turns a texture that wasn't found into a missing texture of the given model, so the
+        // report names the file referencing it.
+        match self {
+            Self::NotFound { name, searched } => Self::MissingTexture {
+                model: model.into(),
+                texture: name,
+                searched,
+            },
+            e => e,
+        }
+    }
+}
+
+impl Display for AssetError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let list = |paths: &[PathBuf]| {
+            paths
+                .iter()
+                .map(|p| format!("'{}'", p.display()))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        match self {
+            Self::NotFound { name, searched } => {
+                write!(f, "could not find '{name}' (searched {})", list(searched))
+            }
+            Self::Parse { path, message } => {
+                write!(f, "could not load '{}': {message}", path.display())
+            }
+            Self::EmptyMesh { path } => write!(f, "model '{}' has no triangles", path.display()),
+            Self::EmptyScene { path } => {
+                write!(f, "scene '{}' has nothing to render", path.display())
+            }
+            Self::MissingTexture {
+                model,
+                texture,
+                searched,
+            } => write!(
+                f,
+                "could not find texture '{texture}' of '{}' (searched {})",
+                model.display(),
+                list(searched)
+            ),
+        }
+    }
+}
+
+impl Error for AssetError {}
+
+impl AssetResolver {
+    pub fn with_search_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.search_dirs.push(dir.into());
+        self
+    }
+
+    pub fn with_cache_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.cache_dir = Some(dir.into());
+        self
+    }
+
+    pub fn cache_dir(&self) -> Option<&Path> {
+        self.cache_dir.as_deref()
+    }
+
+    pub fn search_paths(
+        &self,
+        name: &str,
+        kind: AssetKind,
+        relative_to: Option<&Path>,
+    ) -> Vec<PathBuf> {
+        let mut paths = Vec::new();
+        if let Some(dir) = relative_to {
+            paths.push(dir.join(name));
+        }
+        paths.extend(self.search_dirs.iter().map(|dir| dir.join(name)));
+        if let Ok(dir) = env::var(kind.env_var()) {
+            paths.push(PathBuf::from(dir).join(name));
+        }
+        paths.push(PathBuf::from(name));
+
+        let mut dir = PathBuf::from(kind.subdir());
+        for _ in 0..=PARENT_LEVELS {
+            paths.push(dir.join(name));
+            dir = Path::new("..").join(dir);
+        }
+
+        paths
+    }
+
+    pub fn resolve(
+        &self,
+        name: &str,
+        kind: AssetKind,
+        relative_to: Option<&Path>,
+    ) -> Result<PathBuf, AssetError> {
+        // Returns the first existing file of the search paths.
+        let searched = self.search_paths(name, kind, relative_to);
+        match searched.iter().find(|path| path.exists()) {
+            Some(path) => Ok(path.clone()),
+            None => Err(AssetError::NotFound {
+                name: name.to_owned(),
+                searched,
+            }),
+        }
+    }
+
+    pub fn image(
+        &self,
+        name: &str,
+        relative_to: Option<&Path>,
+    ) -> Result<Arc<RtwImage>, AssetError> {
+        // The cache is keyed by the canonical path, so that different relative paths to the same
+        // file share the image. The lock is held while decoding, so concurrent requests for the
+        // same image don't decode it twice.
+        let path = self.resolve(name, AssetKind::Image, relative_to)?;
+        let key = path.canonicalize().unwrap_or_else(|_| path.clone());
+
+        let mut images = self.images.lock().unwrap();
+        if let Some(image) = images.get(&key) {
+            return Ok(image.clone());
+        }
+        let image = Arc::new(RtwImage::new(&path)?);
+        images.insert(key, image.clone());
+        Ok(image)
+    }
+}
@@ -1,96 +1,668 @@
 use crate::aabb::AABB;
-use crate::hittable::{HitRecord, Hittable};
+use crate::cache::{CacheReader, CacheWriter};
+use crate::hittable::{Hit, HitRecord, Hittable, SurfacePoint};
 use crate::hittable_list::HittableList;
 use crate::interval::Interval;
 use crate::ray::Ray;
+use crate::transform::Transform;
+use crate::vec3::Point;
+use crate::wide_bvh::WideBVH;
+use rayon::prelude::*;
+use std::io;
 use std::sync::Arc;
 
-pub struct BVHNode {
-    left: Arc<dyn Hittable>,
-    right: Arc<dyn Hittable>,
-    bbox: AABB,
+// Cost of visiting a node relative to intersecting a primitive, used by the SAH.
+const TRAVERSAL_COST: f64 = 1.0;
+
+// Refitted trees whose cost grew by more than this factor since they were built are rebuilt.
+const REBUILD_COST_RATIO: f64 = 1.5;
+
+// Number of primitives from which subtrees are built and primitives are binned in parallel.
+const PARALLEL_BUILD_SIZE: usize = 4096;
+
+fn fold_primitives<T: Send>(
+    order: &[u32],
+    init: impl Fn() -> T + Sync + Send,
+    fold: impl Fn(T, u32) -> T + Sync + Send,
+    merge: impl Fn(T, T) -> T + Sync + Send,
+) -> T {
+    // Folds over the primitives listed in order, in parallel for large lists.
+    if order.len() < PARALLEL_BUILD_SIZE {
+        order.iter().fold(init(), |acc, &i| fold(acc, i))
+    } else {
+        order
+            .par_iter()
+            .fold(&init, |acc, &i| fold(acc, i))
+            .reduce(&init, merge)
+    }
 }
 
-impl BVHNode {
-    pub fn new(objects: &mut [Arc<dyn Hittable>]) -> Self {
-        // Build the bounding box of the span of source objects.
-        let mut bbox = AABB::empty();
-        for object_index in 0..objects.len() {
-            bbox = AABB::from_aabbs(&bbox, objects[object_index].bounding_box());
+/// How the BVH builders split a set of primitives in two.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SplitMethod {
+    Median, // At the median centroid along the longest axis
+    Sah,    // At the bin boundary with the lowest cost under the surface area heuristic
+}
+
+/// Settings of the BVH builders, shared by BVHNode and the BVH of a TriangleMesh.
+#[derive(Clone, Debug)]
+pub struct BVHOptions {
+    pub split: SplitMethod,
+    pub bins: usize, // Number of centroid bins evaluated by the SAH along the split axis
+    pub max_leaf_size: usize, // Primitive count above which a set is always split
+    pub wide: bool,  // Collapse the tree into four-wide nodes for traversal
+}
+
+impl Default for BVHOptions {
+    fn default() -> Self {
+        Self {
+            split: SplitMethod::Sah,
+            bins: 12,
+            max_leaf_size: 4,
+            wide: true,
+        }
+    }
+}
+
+impl BVHOptions {
+    pub fn partition(
+        &self,
+        bbox: &AABB,
+        bboxes: &[AABB],
+        centroids: &[Point],
+        order: &mut [u32],
+    ) -> Option<(usize, usize)> {
+        // Splits the primitives listed in order, whose bounds are bbox. Reorders them so that
+        // the primitives of the first child come first and returns their count and the split
+        // axis, or returns None if they should stay together in a leaf. Costs are all scaled
+        // by the surface area of bbox, which doesn't change their order.
+        let count = order.len();
+        if count <= 1 {
+            return None;
+        }
+
+        // The split axis is the longest axis of the centroid bounds. Bounds are computed here
+        // rather than with AABB, which pads thin boxes.
+        let (lo, hi) = fold_primitives(
+            order,
+            || ([f64::INFINITY; 3], [-f64::INFINITY; 3]),
+            |(mut lo, mut hi), i| {
+                let c = &centroids[i as usize];
+                for axis in 0..3 {
+                    lo[axis] = lo[axis].min(c[axis]);
+                    hi[axis] = hi[axis].max(c[axis]);
+                }
+                (lo, hi)
+            },
+            |(lo0, hi0), (lo1, hi1)| {
+                (
+                    [0, 1, 2].map(|axis| lo0[axis].min(lo1[axis])),
+                    [0, 1, 2].map(|axis| hi0[axis].max(hi1[axis])),
+                )
+            },
+        );
+        let axis = (0..3)
+            .max_by(|&a, &b| (hi[a] - lo[a]).total_cmp(&(hi[b] - lo[b])))
+            .unwrap();
+        let extent = hi[axis] - lo[axis];
+
+        // Primitives sharing a centroid can't be told apart, split them arbitrarily if there
+        // are too many for a leaf.
+        if extent <= 0.0 {
+            return (count > self.max_leaf_size).then_some((count / 2, axis));
+        }
+
+        if self.split == SplitMethod::Sah
+            && let Some((bin_split, cost)) =
+                self.sah_split(bboxes, centroids, order, axis, lo[axis], extent)
+        {
+            let cost = TRAVERSAL_COST * bbox.surface_area() + cost;
+            let leaf_cost = count as f64 * bbox.surface_area();
+            if count <= self.max_leaf_size && leaf_cost <= cost {
+                return None;
+            }
+
+            let bin = |i: u32| self.bin(centroids[i as usize][axis], lo[axis], extent);
+            let mut mid = 0;
+            for k in 0..count {
+                if bin(order[k]) <= bin_split {
+                    order.swap(k, mid);
+                    mid += 1;
+                }
+            }
+            return Some((mid, axis));
+        }
+
+        if count <= self.max_leaf_size {
+            return None;
         }
+        let mid = count / 2;
+        order.select_nth_unstable_by(mid, |&a, &b| {
+            centroids[a as usize][axis].total_cmp(&centroids[b as usize][axis])
+        });
+        Some((mid, axis))
+    }
+
+    fn bin(&self, x: f64, min: f64, extent: f64) -> usize {
+        (((x - min) / extent * self.bins as f64) as usize).min(self.bins - 1)
+    }
+
+    fn sah_split(
+        &self,
+        bboxes: &[AABB],
+        centroids: &[Point],
+        order: &[u32],
+        axis: usize,
+        min: f64,
+        extent: f64,
+    ) -> Option<(usize, f64)> {
+        // Sorts the centroids into equal bins along the axis, and returns the last bin of the
+        // first child for the split with the lowest cost, along with that cost. The cost of a
+        // child is its primitive count times its surface area, the probability of a ray hitting
+        // it being proportional to its area. Returns None when the costs aren't finite, e.g. for
+        // unbounded primitives.
+        let (counts, bounds) = fold_primitives(
+            order,
+            || (vec![0usize; self.bins], vec![AABB::empty(); self.bins]),
+            |(mut counts, mut bounds), i| {
+                let b = self.bin(centroids[i as usize][axis], min, extent);
+                counts[b] += 1;
+                bounds[b] = AABB::from_aabbs(&bounds[b], &bboxes[i as usize]);
+                (counts, bounds)
+            },
+            |(mut counts, mut bounds), (counts1, bounds1)| {
+                for b in 0..counts.len() {
+                    counts[b] += counts1[b];
+                    bounds[b] = AABB::from_aabbs(&bounds[b], &bounds1[b]);
+                }
+                (counts, bounds)
+            },
+        );
 
-        let axis = bbox.longest_axis();
+        // Sweep from the right to get the cost of every second child, then from the left.
+        let mut right_costs = vec![0.0; self.bins];
+        let (mut count, mut bbox) = (0, AABB::empty());
+        for b in (1..self.bins).rev() {
+            count += counts[b];
+            bbox = AABB::from_aabbs(&bbox, &bounds[b]);
+            right_costs[b] = count as f64 * bbox.surface_area();
+        }
 
-        let comparator = match axis {
-            0 => Self::box_compare::<0>,
-            1 => Self::box_compare::<1>,
-            _ => Self::box_compare::<2>,
+        let mut best: Option<(usize, f64)> = None;
+        let (mut count, mut bbox) = (0, AABB::empty());
+        for b in 0..self.bins - 1 {
+            count += counts[b];
+            bbox = AABB::from_aabbs(&bbox, &bounds[b]);
+            if count == 0 || count == order.len() {
+                continue;
+            }
+            let cost = count as f64 * bbox.surface_area() + right_costs[b + 1];
+            if best.is_none_or(|(_, best_cost)| cost < best_cost) {
+                best = Some((b, cost));
+            }
+        }
+
+        best.filter(|(_, cost)| cost.is_finite())
+    }
+}
+
+/// A node of the flattened BVH.
+///
+/// The first child of an interior node directly follows it in the node array, so only the
+/// index of the second child is stored.
+pub struct LinearNode {
+    pub bbox: AABB,
+    pub offset: u32, // Interior node: index of the second child. Leaf: first primitive
+    pub count: u32,  // Number of primitives of a leaf, 0 for interior nodes
+    pub axis: u8,    // Split axis of an interior node, the first child lies on its negative side
+}
+
+/// Bounding volume hierarchy flattened into an array of nodes, in depth-first order.
+///
+/// The BVH only knows the bounds of the primitives, it is built over a list of boxes and
+/// returns the order in which the primitives must be stored, so that every leaf covers a range
+/// of consecutive primitives. Primitives are intersected by the caller during the traversal.
+/// The tree keeps either its binary nodes or, with `BVHOptions::wide`, the four-wide nodes
+/// collapsed from them, never both. A BVH over no primitives is a single leaf with no
+/// primitives and an empty box, which no ray enters.
+pub struct LinearBVH {
+    layout: Layout,
+}
+
+// The nodes of a LinearBVH, in the layout used for traversal.
+enum Layout {
+    Binary(Vec<LinearNode>),
+    Wide(WideBVH),
+}
+
+impl LinearBVH {
+    pub fn new(bboxes: &[AABB], options: &BVHOptions) -> (Self, Vec<u32>) {
+        // Returns the BVH and the primitive indices in storage order. A four-wide tree is
+        // collapsed from the binary one, which is dropped afterwards.
+
+        let centroids: Vec<Point> = bboxes.iter().map(AABB::centroid).collect();
+        let mut order: Vec<u32> = (0..bboxes.len() as u32).collect();
+        let nodes = Self::build(&mut order, 0, bboxes, &centroids, options);
+        let layout = if options.wide {
+            Layout::Wide(WideBVH::new(&nodes))
+        } else {
+            Layout::Binary(nodes)
         };
+        (Self { layout }, order)
+    }
+
+    fn build(
+        order: &mut [u32],
+        first: usize,
+        bboxes: &[AABB],
+        centroids: &[Point],
+        options: &BVHOptions,
+    ) -> Vec<LinearNode> {
+        // Large subtrees are built in parallel, each into a node array of its own. The arrays
+        // are then joined behind their parent node, shifting the child indices they hold.
+        if order.len() < PARALLEL_BUILD_SIZE {
+            let mut nodes = Vec::with_capacity(2 * order.len() / options.max_leaf_size + 1);
+            Self::build_node(&mut nodes, order, first, bboxes, centroids, options);
+            return nodes;
+        }
+
+        let bbox = Self::bounds(order, bboxes);
+        let Some((mid, axis)) = options.partition(&bbox, bboxes, centroids, order) else {
+            return vec![LinearNode {
+                bbox,
+                offset: first as u32,
+                count: order.len() as u32,
+                axis: 0,
+            }];
+        };
+
+        let (left, right) = order.split_at_mut(mid);
+        let (left, right) = rayon::join(
+            || Self::build(left, first, bboxes, centroids, options),
+            || Self::build(right, first + mid, bboxes, centroids, options),
+        );
+
+        let mut nodes = Vec::with_capacity(1 + left.len() + right.len());
+        nodes.push(LinearNode {
+            bbox,
+            offset: (1 + left.len()) as u32,
+            count: 0,
+            axis: axis as u8,
+        });
+        let right_shift = 1 + left.len() as u32;
+        for (children, shift) in [(left, 1), (right, right_shift)] {
+            nodes.extend(children.into_iter().map(|mut node| {
+                if node.count == 0 {
+                    node.offset += shift;
+                }
+                node
+            }));
+        }
+        nodes
+    }
+
+    fn build_node(
+        nodes: &mut Vec<LinearNode>,
+        order: &mut [u32],
+        first: usize,
+        bboxes: &[AABB],
+        centroids: &[Point],
+        options: &BVHOptions,
+    ) {
+        let bbox = Self::bounds(order, bboxes);
+        let split = options.partition(&bbox, bboxes, centroids, order);
+        let node_index = nodes.len();
+        nodes.push(LinearNode {
+            bbox,
+            offset: first as u32,
+            count: order.len() as u32,
+            axis: 0,
+        });
+        let Some((mid, axis)) = split else {
+            return;
+        };
+
+        let (left, right) = order.split_at_mut(mid);
+        Self::build_node(nodes, left, first, bboxes, centroids, options);
+        let right_index = nodes.len();
+        Self::build_node(nodes, right, first + mid, bboxes, centroids, options);
+
+        let node = &mut nodes[node_index];
+        node.offset = right_index as u32;
+        node.count = 0;
+        node.axis = axis as u8;
+    }
+
+    fn bounds(order: &[u32], bboxes: &[AABB]) -> AABB {
+        fold_primitives(
+            order,
+            AABB::empty,
+            |bbox, i| AABB::from_aabbs(&bbox, &bboxes[i as usize]),
+            |a, b| AABB::from_aabbs(&a, &b),
+        )
+    }
+
+    pub fn bounding_box(&self) -> &AABB {
+        match &self.layout {
+            Layout::Binary(nodes) => &nodes[0].bbox,
+            Layout::Wide(wide) => wide.bounding_box(),
+        }
+    }
+
+    pub fn transform(&mut self, transform: &Transform) {
+        // Replaces the node boxes by the bounds of their transformed corners, keeping the
+        // structure of the tree.
+        match &mut self.layout {
+            Layout::Binary(nodes) => {
+                for node in nodes {
+                    node.bbox = transform.bbox(&node.bbox);
+                }
+            }
+            Layout::Wide(wide) => wide.transform(transform),
+        }
+    }
 
-        let object_span = objects.len();
+    fn is_empty(&self) -> bool {
+        match &self.layout {
+            // The root of an empty tree is a leaf, although its count of 0 marks interior nodes.
+            Layout::Binary(nodes) => nodes.len() == 1 && nodes[0].count == 0,
+            Layout::Wide(wide) => wide.is_empty(),
+        }
+    }
 
-        let (left, right): (Arc<dyn Hittable>, Arc<dyn Hittable>) = match object_span {
-            1 => {
-                let node = objects[0].clone();
-                (node.clone(), node)
+    pub fn write_cache(&self, out: &mut CacheWriter) {
+        match &self.layout {
+            Layout::Binary(nodes) => {
+                out.u8(0);
+                out.len(nodes.len());
+                for node in nodes {
+                    out.aabb(&node.bbox);
+                    out.u32(node.offset);
+                    out.u32(node.count);
+                    out.u8(node.axis);
+                }
             }
-            2 => {
-                let a = objects[0].clone();
-                let b = objects[1].clone();
-                (a, b)
+            Layout::Wide(wide) => {
+                out.u8(1);
+                wide.write_cache(out);
             }
+        }
+    }
+
+    pub fn read_cache(input: &mut CacheReader, primitive_count: usize) -> io::Result<Self> {
+        let layout = match input.u8()? {
+            0 => Layout::Binary(Self::read_binary_cache(input, primitive_count)?),
+            1 => Layout::Wide(WideBVH::read_cache(input, primitive_count)?),
             _ => {
-                objects.sort_by(|a, b| comparator(a.as_ref(), b.as_ref()));
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "unknown BVH layout in cache file",
+                ));
+            }
+        };
+        Ok(Self { layout })
+    }
 
-                let mid = object_span / 2;
-                let (left_objs, right_objs) = objects.split_at_mut(mid);
-                let left_node = Arc::new(BVHNode::new(left_objs));
-                let right_node = Arc::new(BVHNode::new(right_objs));
-                (left_node, right_node)
+    fn read_binary_cache(
+        input: &mut CacheReader,
+        primitive_count: usize,
+    ) -> io::Result<Vec<LinearNode>> {
+        // Checks that the child indices and leaf ranges read stay within the tree and the
+        // primitives, since the traversal relies on them.
+        const NODE_SIZE: usize = 6 * 8 + 4 + 4 + 1;
+        let len = input.len(NODE_SIZE)?;
+        let mut nodes = Vec::with_capacity(len);
+        for i in 0..len {
+            let node = LinearNode {
+                bbox: input.aabb()?,
+                offset: input.u32()?,
+                count: input.u32()?,
+                axis: input.u8()?,
+            };
+            let (offset, count) = (node.offset as usize, node.count as usize);
+            let valid = match count {
+                0 if len == 1 => offset == 0,
+                0 => i + 1 < offset && offset < len && node.axis < 3,
+                _ => offset + count <= primitive_count,
+            };
+            if !valid {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "invalid BVH node in cache file",
+                ));
             }
+            nodes.push(node);
+        }
+
+        if nodes.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "empty BVH in cache file",
+            ));
+        }
+        Ok(nodes)
+    }
+
+    pub fn refit(&mut self, bboxes: &[AABB]) {
+        // Recomputes the node boxes from the primitive boxes, given in storage order, keeping
+        // the structure of the tree. Children are stored after their parent, so a backward
+        // pass updates them first.
+        if self.is_empty() {
+            return;
+        }
+        let nodes = match &mut self.layout {
+            Layout::Binary(nodes) => nodes,
+            Layout::Wide(wide) => return wide.refit(bboxes),
         };
+        for i in (0..nodes.len()).rev() {
+            let node = &nodes[i];
+            let (offset, count) = (node.offset as usize, node.count as usize);
+            let bbox = if count > 0 {
+                bboxes[offset..offset + count]
+                    .iter()
+                    .fold(AABB::empty(), |bbox, b| AABB::from_aabbs(&bbox, b))
+            } else {
+                AABB::from_aabbs(&nodes[i + 1].bbox, &nodes[offset].bbox)
+            };
+            nodes[i].bbox = bbox;
+        }
+    }
 
-        Self { left, right, bbox }
+    pub fn cost(&self) -> f64 {
+        // Expected cost of tracing a ray through the tree under the surface area heuristic,
+        // relative to the cost of intersecting a primitive. It grows as refitted boxes come
+        // to overlap. Every node visited costs a traversal step, in both layouts.
+        if self.is_empty() {
+            return 0.0;
+        }
+        let cost = |count: u32, bbox: &AABB| match count {
+            0 => TRAVERSAL_COST * bbox.surface_area(),
+            count => count as f64 * bbox.surface_area(),
+        };
+        let root_area = self.bounding_box().surface_area();
+        let total = match &self.layout {
+            Layout::Binary(nodes) => nodes.iter().map(|node| cost(node.count, &node.bbox)).sum(),
+            Layout::Wide(wide) => {
+                TRAVERSAL_COST * root_area
+                    + wide
+                        .children()
+                        .map(|(bbox, count)| cost(count, &bbox))
+                        .sum::<f64>()
+            }
+        };
+        total / root_area
     }
 
-    fn box_compare<const AXIS: usize>(a: &dyn Hittable, b: &dyn Hittable) -> std::cmp::Ordering {
-        a.bounding_box()[AXIS]
-            .min
-            .total_cmp(&b.bounding_box()[AXIS].min)
+    pub fn hit(
+        &self,
+        r: &Ray,
+        ray_t: Interval,
+        mut hit_primitive: impl FnMut(usize, Interval) -> Option<f64>,
+    ) {
+        // Visits the leaves the ray passes through, calling hit_primitive on their primitives
+        // with the interval left to search. It returns the distance of a closer hit, which
+        // then shortens the interval. The child on the side the ray comes from is visited
+        // first, so that close hits cut off the boxes further away. A distance at or below
+        // ray_t.min leaves nothing to search and ends the traversal.
+        if self.is_empty() {
+            return;
+        }
+        let nodes = match &self.layout {
+            Layout::Binary(nodes) => nodes,
+            Layout::Wide(wide) => return wide.hit(r, ray_t, hit_primitive),
+        };
+
+        let inv_dir = r.inv_direction();
+        let dir_is_neg = [inv_dir[0] < 0.0, inv_dir[1] < 0.0, inv_dir[2] < 0.0];
+        let mut t_max = ray_t.max;
+
+        let mut stack = Vec::with_capacity(64);
+        stack.push(0);
+        while let Some(node_index) = stack.pop() {
+            let node = &nodes[node_index];
+            if !node.bbox.hit(r, Interval::from(ray_t.min, t_max)) {
+                continue;
+            }
+
+            if node.count == 0 {
+                if dir_is_neg[node.axis as usize] {
+                    stack.push(node_index + 1);
+                    stack.push(node.offset as usize);
+                } else {
+                    stack.push(node.offset as usize);
+                    stack.push(node_index + 1);
+                }
+                continue;
+            }
+
+            let first = node.offset as usize;
+            for i in first..first + node.count as usize {
+                if let Some(t) = hit_primitive(i, Interval::from(ray_t.min, t_max)) {
+                    t_max = t;
+                    if t_max <= ray_t.min {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn occluded(
+        &self,
+        r: &Ray,
+        ray_t: Interval,
+        mut occluded_by: impl FnMut(usize, Interval) -> bool,
+    ) -> bool {
+        // Whether occluded_by is true for any primitive, stopping at the first one.
+        let mut occluded = false;
+        self.hit(r, ray_t, |i, interval| {
+            occluded = occluded_by(i, interval);
+            occluded.then_some(ray_t.min)
+        });
+        occluded
     }
 }
 
-impl Hittable for BVHNode {
-    fn hit(&self, r: &Ray, mut ray_t: Interval) -> Option<HitRecord> {
-        if !self.bbox.hit(r, ray_t) {
-            return None;
+/// BVH over arbitrary hittable objects.
+pub struct BVHNode {
+    objects: Vec<Arc<dyn Hittable>>, // In the storage order of the BVH
+    order: Vec<u32>,                 // Index given to new of each stored object
+    options: BVHOptions,
+    built_cost: f64, // Cost of the tree when it was last built
+    bvh: LinearBVH,
+}
+
+impl BVHNode {
+    pub fn new(objects: &[Arc<dyn Hittable>]) -> Self {
+        Self::with_options(objects, &BVHOptions::default())
+    }
+
+    pub fn with_options(objects: &[Arc<dyn Hittable>], options: &BVHOptions) -> Self {
+        let bboxes: Vec<AABB> = objects.iter().map(|o| o.bounding_box().clone()).collect();
+        let (bvh, order) = LinearBVH::new(&bboxes, options);
+        Self {
+            objects: order.iter().map(|&i| objects[i as usize].clone()).collect(),
+            order,
+            options: options.clone(),
+            built_cost: bvh.cost(),
+            bvh,
         }
+    }
 
-        let hit_left = self.left.hit(r, ray_t);
-        let hit_left = if let Some(rec) = hit_left {
-            ray_t.max = rec.t;
-            Some(rec)
-        } else {
-            None
-        };
-        let hit_right = self.right.hit(r, ray_t);
+    pub fn update(&mut self, objects: &[Arc<dyn Hittable>]) -> bool {
+        // Replaces the objects by moved versions of them, given in the same order as to new.
+        // The tree is refitted to their new boxes, or rebuilt if refitting made its cost grow
+        // too much. Returns whether the tree was rebuilt.
+        assert_eq!(
+            objects.len(),
+            self.objects.len(),
+            "a BVH update needs as many objects as it was built with"
+        );
+
+        self.objects = self
+            .order
+            .iter()
+            .map(|&i| objects[i as usize].clone())
+            .collect();
+        let bboxes: Vec<AABB> = self
+            .objects
+            .iter()
+            .map(|o| o.bounding_box().clone())
+            .collect();
+        self.bvh.refit(&bboxes);
+
+        let rebuild = self.bvh.cost() > REBUILD_COST_RATIO * self.built_cost;
+        if rebuild {
+            *self = Self::with_options(objects, &self.options);
+        }
+        rebuild
+    }
+
+    pub fn cost(&self) -> f64 {
+        self.bvh.cost()
+    }
+}
+
+impl Hittable for BVHNode {
+    fn closest_hit(&self, r: &Ray, ray_t: Interval) -> Option<Hit> {
+        let mut closest = None;
+        self.bvh.hit(r, ray_t, |i, interval| {
+            let mut hit = self.objects[i].closest_hit(r, interval)?;
+            hit.push(i);
+            let t = hit.t;
+            closest = Some(hit);
+            Some(t)
+        });
+        closest
+    }
+
+    fn interaction(&self, r: &Ray, hit: &mut Hit) -> HitRecord {
+        self.objects[hit.pop()].interaction(r, hit)
+    }
+
+    fn surface_point(&self, r: &Ray, hit: &mut Hit) -> SurfacePoint {
+        self.objects[hit.pop()].surface_point(r, hit)
+    }
 
-        hit_right.or(hit_left)
+    fn occluded(&self, r: &Ray, ray_t: Interval) -> bool {
+        self.bvh.occluded(r, ray_t, |i, interval| {
+            self.objects[i].occluded(r, interval)
+        })
     }
 
     fn bounding_box(&self) -> &AABB {
-        &self.bbox
+        self.bvh.bounding_box()
     }
 }
 
 impl From<HittableList> for BVHNode {
-    fn from(mut value: HittableList) -> Self {
-        Self::new(value.objects.as_mut_slice())
+    fn from(value: HittableList) -> Self {
+        Self::new(&value.objects)
     }
 }
 
 impl From<Vec<Arc<dyn Hittable>>> for BVHNode {
-    fn from(mut value: Vec<Arc<dyn Hittable>>) -> Self {
-        Self::new(value.as_mut_slice())
+    fn from(value: Vec<Arc<dyn Hittable>>) -> Self {
+        Self::new(&value)
     }
 }
@@ -0,0 +1,332 @@
+use crate::aabb::AABB;
+use crate::interval::Interval;
+use crate::vec3::{Real, Vec3f64};
+use std::fs;
+use std::hash::Hasher;
+use std::io;
+use std::path::Path;
+
+// Start of every cache file, the version is bumped whenever the layout changes. Files also
+// record the size of Real, since BVH boxes computed from vertices of one precision don't
+// bound the vertices rounded to the other.
+const MAGIC: &[u8; 8] = b"RTWCACHE";
+const VERSION: u32 = 4;
+const PRECISION: u8 = size_of::<Real>() as u8;
+
+/// 64-bit FNV-1a hash, used to key cache files by the contents of their source file.
+///
+/// Unlike the standard library hashers it gives the same value on every run.
+pub struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0 ^ b as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Binary cache file being written, little-endian throughout.
+///
+/// The data is collected in memory and written in one go by `save`, so a failed write never
+/// leaves a truncated file behind.
+pub struct CacheWriter {
+    data: Vec<u8>,
+}
+
+impl CacheWriter {
+    pub fn new(key: u64) -> Self {
+        let mut writer = Self {
+            data: MAGIC.to_vec(),
+        };
+        writer.u32(VERSION);
+        writer.u8(PRECISION);
+        writer.u64(key);
+        writer
+    }
+
+    pub fn u8(&mut self, x: u8) {
+        self.data.push(x);
+    }
+
+    pub fn u32(&mut self, x: u32) {
+        self.data.extend_from_slice(&x.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, x: u64) {
+        self.data.extend_from_slice(&x.to_le_bytes());
+    }
+
+    pub fn f64(&mut self, x: f64) {
+        self.data.extend_from_slice(&x.to_le_bytes());
+    }
+
+    pub fn vec3(&mut self, v: &Vec3f64) {
+        for i in 0..3 {
+            self.f64(v[i]);
+        }
+    }
+
+    pub fn aabb(&mut self, bbox: &AABB) {
+        for i in 0..3 {
+            self.f64(bbox[i].min);
+            self.f64(bbox[i].max);
+        }
+    }
+
+    pub fn len(&mut self, len: usize) {
+        self.u64(len as u64);
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        // Writes to a temporary file renamed over the target, so that readers only ever see
+        // complete files.
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, &self.data)?;
+        fs::rename(&tmp, path)
+    }
+}
+
+/// Binary cache file being read, the counterpart of `CacheWriter`.
+///
+/// The whole file is loaded up front, and every read checks that the data is there, so a
+/// corrupted file gives an error rather than a huge allocation or a panic.
+pub struct CacheReader {
+    data: Vec<u8>,
+    pos: usize,
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl CacheReader {
+    pub fn open(path: &Path, key: u64) -> io::Result<Option<Self>> {
+        // Returns None if there is no cache file, or if it was written for another key, by
+        // another version or with another precision.
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let mut reader = Self { data, pos: 0 };
+        let magic = reader.bytes(MAGIC.len())?;
+        if magic != MAGIC
+            || reader.u32()? != VERSION
+            || reader.u8()? != PRECISION
+            || reader.u64()? != key
+        {
+            return Ok(None);
+        }
+        Ok(Some(reader))
+    }
+
+    fn bytes(&mut self, n: usize) -> io::Result<&[u8]> {
+        if self.data.len() - self.pos < n {
+            return Err(invalid("truncated cache file"));
+        }
+        self.pos += n;
+        Ok(&self.data[self.pos - n..self.pos])
+    }
+
+    pub fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    pub fn f64(&mut self) -> io::Result<f64> {
+        Ok(f64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    pub fn vec3(&mut self) -> io::Result<Vec3f64> {
+        Ok(Vec3f64::new(self.f64()?, self.f64()?, self.f64()?))
+    }
+
+    pub fn aabb(&mut self) -> io::Result<AABB> {
+        let mut axis = || Ok::<_, io::Error>(Interval::from(self.f64()?, self.f64()?));
+        Ok(AABB::new(axis()?, axis()?, axis()?))
+    }
+
+    pub fn len(&mut self, item_size: usize) -> io::Result<usize> {
+        // Reads the length of an array of items of the given size, which must fit in the rest
+        // of the file.
+        let len = self.u64()?;
+        if len > ((self.data.len() - self.pos) / item_size) as u64 {
+            return Err(invalid("cache array longer than the file"));
+        }
+        Ok(len as usize)
+    }
+
+    pub fn finish(&self) -> io::Result<()> {
+        if self.pos != self.data.len() {
+            return Err(invalid("trailing data in cache file"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    const KEY: u64 = 0x0123_4567_89ab_cdef;
+
+    // A file of its own for every test, as tests run in parallel, removed with its directory
+    // once the last test is done with it.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("rtw-cache-test-{}", std::process::id()));
+            Self(dir.join(format!("{name}.bin")))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            fs::remove_file(&self.0).ok();
+            if let Some(dir) = self.0.parent() {
+                fs::remove_dir(dir).ok();
+            }
+        }
+    }
+
+    fn write_sample(path: &Path) -> Vec<u8> {
+        // Saves one value of every kind and returns the file contents.
+        let mut out = CacheWriter::new(KEY);
+        out.u8(7);
+        out.u32(0xdead_beef);
+        out.u64(u64::MAX);
+        out.f64(-0.25);
+        out.vec3(&Vec3f64::new(1.0, 2.0, 3.0));
+        out.aabb(&AABB::new(
+            Interval::from(-1.0, 1.0),
+            Interval::from(-2.0, 2.0),
+            Interval::from(-3.0, f64::INFINITY),
+        ));
+        out.len(2);
+        out.u32(1);
+        out.u32(2);
+        out.save(path).unwrap();
+        fs::read(path).unwrap()
+    }
+
+    fn read_sample(input: &mut CacheReader) -> io::Result<()> {
+        assert_eq!(input.u8()?, 7);
+        assert_eq!(input.u32()?, 0xdead_beef);
+        assert_eq!(input.u64()?, u64::MAX);
+        assert_eq!(input.f64()?, -0.25);
+        assert_eq!(input.vec3()?, Vec3f64::new(1.0, 2.0, 3.0));
+        let bbox = input.aabb()?;
+        assert_eq!((bbox[0].min, bbox[0].max), (-1.0, 1.0));
+        assert_eq!((bbox[2].min, bbox[2].max), (-3.0, f64::INFINITY));
+        assert_eq!(input.len(4)?, 2);
+        assert_eq!((input.u32()?, input.u32()?), (1, 2));
+        input.finish()
+    }
+
+    #[test]
+    fn round_trip() {
+        let file = TempFile::new("round_trip");
+        let path = &file.0;
+        write_sample(path);
+        assert!(!path.with_extension("tmp").exists());
+        let mut input = CacheReader::open(path, KEY).unwrap().unwrap();
+        read_sample(&mut input).unwrap();
+    }
+
+    #[test]
+    fn missing_file() {
+        assert!(
+            CacheReader::open(&TempFile::new("missing").0, KEY)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn other_key_version_or_precision() {
+        // The header is the magic, the version at byte 8, the precision at 12 and the key at 13.
+        let file = TempFile::new("header");
+        let path = &file.0;
+        let data = write_sample(path);
+        assert!(CacheReader::open(path, KEY + 1).unwrap().is_none());
+        for byte in [0, 8, 12, 13] {
+            let mut data = data.clone();
+            data[byte] ^= 1;
+            fs::write(path, data).unwrap();
+            assert!(
+                CacheReader::open(path, KEY).unwrap().is_none(),
+                "byte {byte}"
+            );
+        }
+    }
+
+    #[test]
+    fn truncated_file() {
+        let file = TempFile::new("truncated");
+        let path = &file.0;
+        let data = write_sample(path);
+        fs::write(path, &data[..4]).unwrap();
+        assert!(CacheReader::open(path, KEY).is_err());
+        // Every cut inside the values is found by the read that crosses it, or by the length.
+        for len in 21..data.len() {
+            fs::write(path, &data[..len]).unwrap();
+            let mut input = CacheReader::open(path, KEY).unwrap().unwrap();
+            let err = read_sample(&mut input).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "length {len}");
+        }
+    }
+
+    #[test]
+    fn oversized_length() {
+        let file = TempFile::new("oversized");
+        let path = &file.0;
+        let mut out = CacheWriter::new(KEY);
+        out.len(3);
+        out.u64(1);
+        out.len(usize::MAX);
+        out.save(path).unwrap();
+
+        // 16 bytes follow the first length: three items of 8 bytes don't fit, of 4 bytes do.
+        let mut input = CacheReader::open(path, KEY).unwrap().unwrap();
+        assert!(input.len(8).is_err());
+        let mut input = CacheReader::open(path, KEY).unwrap().unwrap();
+        assert_eq!(input.len(4).unwrap(), 3);
+        input.u64().unwrap();
+        assert!(input.len(1).is_err());
+    }
+
+    #[test]
+    fn trailing_data() {
+        let file = TempFile::new("trailing");
+        let path = &file.0;
+        let mut data = write_sample(path);
+        data.push(0);
+        fs::write(path, data).unwrap();
+        let mut input = CacheReader::open(path, KEY).unwrap().unwrap();
+        assert!(read_sample(&mut input).is_err());
+    }
+}
@@ -1,14 +1,26 @@
-use crate::color::{Color, ColorU8};
+use crate::color::Color;
+use crate::denoise::Denoiser;
+use crate::filter::Filter;
+use crate::framebuffer::FrameBuffer;
 use crate::hittable::Hittable;
 use crate::interval::Interval;
-use crate::ray::Ray;
+use crate::ray::{Ray, RayDifferential};
 use crate::rtweekend::degrees_to_radians;
+use crate::sampler::{Sampler, SamplerKind};
 use crate::vec3::{Point, Vec3f64};
-use image::{ImageBuffer, RgbImage};
 use rayon::prelude::*;
 use std::io::{self, Write};
+use std::ops::Range;
 use std::sync::Arc;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+// Number of scanlines rendered by each parallel task.
+const BAND_HEIGHT: usize = 8;
+
+// Smallest filter weight per sample of a pixel that is normalized. Filters with negative lobes,
+// such as Mitchell and Lanczos, can sum to almost nothing, and dividing by that would blow the
+// pixel up. Such pixels get the plain average of their own samples instead.
+const MIN_PIXEL_WEIGHT: f64 = 1e-3;
 
 #[derive(Default)]
 pub struct Camera {
@@ -27,6 +39,13 @@ pub struct Camera {
     pub defocus_angle: f64, // Variation angle of rays through each pixel
     pub focus_dist: f64,    // Distance from camera lookfrom point to plane of perfect focus
 
+    pub sampler: SamplerKind, // Generator of the sample values along each path
+    pub filter: Filter,       // Pixel reconstruction filter
+    pub denoiser: Option<Denoiser>, // Post-process filter for the rendered image
+
+    pub roulette_min_depth: Option<i32>, // Bounces before Russian roulette may end a path
+    pub max_sample_radiance: Option<f64>, // Per-sample radiance clamp, biased (previews only)
+
     image_height: i32,        // Rendered image height
     pixel_samples_scale: f64, // Color scale factor for a sum of pixel samples
     center: Point,            // Camera center
@@ -38,65 +57,147 @@ pub struct Camera {
     w: Vec3f64,
     defocus_disk_u: Vec3f64, // Defocus disk horizontal radius
     defocus_disk_v: Vec3f64, // Defocus disk vertical radius
+    rays_traced: AtomicU64,  // Count of rays traced by all renders of this camera
 }
 
 impl Camera {
     pub fn render(&self, world: &dyn Hittable, output_path: &str) -> io::Result<()> {
+        let mut fb = self.render_buffer(world);
+        let mut stderr = io::stderr();
+
+        if let Some(denoiser) = &self.denoiser {
+            writeln!(stderr, "\rDenoising ...")?;
+            fb.color = denoiser.apply(&fb);
+        }
+
+        // Output
+        writeln!(stderr, "\rSaving image to {output_path} ...")?;
+        fb.to_image().save(output_path).map_err(io::Error::other)?;
+
+        writeln!(stderr, "\rDone.")?;
+        Ok(())
+    }
+
+    pub fn render_buffer(&self, world: &dyn Hittable) -> FrameBuffer {
         let width = self.image_width as usize;
         let height = self.image_height as usize;
-        let samples = self.samples_per_pixel;
-        let max_depth = self.max_depth;
-        let scale = self.pixel_samples_scale;
+        let with_guides = self.denoiser.is_some();
 
-        // Allocate a buffer for all pixels
-        let mut buffer = vec![0u8; width * height * 3];
+        // Number of neighbouring pixels a sample can splat into in each direction.
+        let reach = (self.filter.radius() - 0.5).ceil().max(0.0) as usize;
+        // Drawn once per render, so that every band samples its pixels from the same sequence.
+        let seed = rand::random();
 
         // 进度计数器
         let counter = Arc::new(AtomicUsize::new(0));
-        let mut stderr = io::stderr();
-
-        // 按行并行渲染
-        buffer
-            .par_chunks_mut(width * 3)
-            .enumerate()
-            .for_each(|(j, row3)| {
-                for i in 0..width {
-                    let mut color = Color::zero();
-                    for _ in 0..samples {
-                        let r = self.get_ray(i, j);
-                        color += self.ray_color(&r, max_depth, world);
+        let stderr = io::stderr();
+
+        // 按行块并行渲染，每块累积到自己的缓冲区，再合并
+        let bands: Vec<Band> = (0..height)
+            .step_by(BAND_HEIGHT)
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .map(|j0| {
+                let rows = j0..(j0 + BAND_HEIGHT).min(height);
+                let mut band = Band::new(rows, reach, width, height, with_guides);
+                let mut sampler = self.sampler.create(self.samples_per_pixel as u32, seed);
+                for j in band.rows.clone() {
+                    for i in 0..width {
+                        self.render_pixel(i, j, world, &mut band, sampler.as_mut());
                     }
-                    color *= scale;
-
-                    // 把 [0,1] 浮点色值转换到 [0,255]
-                    let color: ColorU8 = color.into();
-                    {
-                        let i = i * 3;
-                        row3[i] = color[0];
-                        row3[i + 1] = color[1];
-                        row3[i + 2] = color[2];
+
+                    self.rays_traced.fetch_add(band.rays, Ordering::Relaxed);
+                    band.rays = 0;
+
+                    // 更新并输出进度
+                    let prev = counter.fetch_add(1, Ordering::SeqCst);
+                    if prev.is_multiple_of(10) {
+                        let mut err = stderr.lock();
+                        write!(err, "\rScanlines remaining: {}    ", height - prev).ok();
+                        err.flush().ok();
                     }
                 }
+                band
+            })
+            .collect();
+        writeln!(stderr.lock(), "\rFinish rendering.                 ").ok();
+
+        // Sum the splats of all bands, then normalize each pixel by its total filter weight.
+        let mut fb = FrameBuffer::new(width, height, with_guides);
+        let mut weight = vec![0.0; width * height];
+        let mut average = vec![Color::zero(); width * height];
+        for band in bands {
+            let splat_offset = band.splat_rows.start * width;
+            for (k, (sum, w)) in band.sum.into_iter().zip(band.weight).enumerate() {
+                fb.color[splat_offset + k] += sum;
+                weight[splat_offset + k] += w;
+            }
+
+            let range = (band.rows.start * width)..(band.rows.end * width);
+            average[range.clone()].clone_from_slice(&band.average);
+            if with_guides {
+                fb.albedo[range.clone()].clone_from_slice(&band.albedo);
+                fb.normal[range.clone()].clone_from_slice(&band.normal);
+                fb.depth[range].copy_from_slice(&band.depth);
+            }
+        }
+        let min_weight = MIN_PIXEL_WEIGHT * self.samples_per_pixel as f64;
+        for ((color, w), average) in fb.color.iter_mut().zip(weight).zip(average) {
+            if w > min_weight {
+                *color /= w;
+            } else {
+                *color = average;
+            }
+        }
+
+        fb
+    }
 
-                // 更新并输出进度
-                let prev = counter.fetch_add(1, Ordering::SeqCst);
-                if prev % 10 == 0 {
-                    let mut err = stderr.lock();
-                    write!(err, "\rScanlines remaining: {}    ", height - prev).ok();
-                    err.flush().ok();
+    fn render_pixel(
+        &self,
+        i: usize,
+        j: usize,
+        world: &dyn Hittable,
+        band: &mut Band,
+        sampler: &mut dyn Sampler,
+    ) {
+        let mut guides = band.has_guides().then(Guides::default);
+        let mut sum = Color::zero();
+
+        for s in 0..self.samples_per_pixel {
+            sampler.start_pixel_sample(i, j, s as u32);
+            let offset = Self::sample_square(sampler);
+            let r = self.get_ray(i, j, &offset, sampler);
+
+            let mut color = self.ray_color(r, world, sampler, &mut band.rays, guides.as_mut());
+            if let Some(max_radiance) = self.max_sample_radiance {
+                // Scale the whole sample down rather than clamping channels, to keep its hue.
+                let peak = color[0].max(color[1]).max(color[2]);
+                if peak > max_radiance {
+                    color *= max_radiance / peak;
                 }
-            });
-        writeln!(stderr, "\rFinish rendering.                 ")?;
+            }
+            sum += &color;
+            band.splat(
+                i as f64 + offset.x(),
+                j as f64 + offset.y(),
+                &color,
+                &self.filter,
+            );
+        }
 
-        // Output
-        writeln!(stderr, "\rSaving image to {output_path} ...")?;
-        let img: RgbImage = ImageBuffer::from_raw(width as u32, height as u32, buffer)
-            .expect("from_raw failed: buffer size incorrect");
-        img.save(output_path)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        let scale = self.pixel_samples_scale;
+        let k = (j - band.rows.start) * band.width + i;
+        band.average[k] = sum * scale;
+        if let Some(guides) = guides {
+            band.albedo[k] = guides.albedo * scale;
+            band.normal[k] = guides.normal * scale;
+            band.depth[k] = guides.depth * scale;
+        }
+    }
 
-        writeln!(stderr, "\rDone.")?;
-        Ok(())
+    pub fn rays_traced(&self) -> u64 {
+        self.rays_traced.load(Ordering::Relaxed)
     }
 
     pub fn with_initialized(mut self) -> Self {
@@ -152,64 +253,181 @@ impl Camera {
         self
     }
 
-    fn get_ray(&self, i: usize, j: usize) -> Ray {
-        // Construct a camera ray originating from the origin and directed at randomly sampled
-        // point around the pixel location i, j.
+    fn get_ray(&self, i: usize, j: usize, offset: &Vec3f64, sampler: &mut dyn Sampler) -> Ray {
+        // Construct a camera ray originating from the origin and directed at the sampled point
+        // offset from the pixel location i, j.
 
-        let offset = Self::sample_square();
         let pixel_sample = &self.pixel00_loc
             + &self.pixel_delta_u * (i as f64 + offset.x())
             + &self.pixel_delta_v * (j as f64 + offset.y());
 
+        // Always draw the lens sample so the following dimensions don't depend on the camera.
+        let lens_sample = sampler.get_2d();
         let ray_origin = if self.defocus_angle < 1e-3 {
             self.center.clone()
         } else {
-            self.defocus_disk_sample()
+            self.defocus_disk_sample(lens_sample)
         };
         let ray_direction = pixel_sample - &ray_origin;
-        let ray_time = rand::random_range(0.0..1.0);
+        let ray_time = sampler.get_1d();
+
+        // The offset rays go through the neighbouring pixels. With many samples per pixel each
+        // sample only stands for part of the pixel, so textures are filtered over less of it.
+        let spacing = (1.0 / (self.samples_per_pixel as f64).sqrt()).max(0.125);
+        let differential = RayDifferential {
+            rx_origin: ray_origin.clone(),
+            rx_direction: &ray_direction + &self.pixel_delta_u * spacing,
+            ry_origin: ray_origin.clone(),
+            ry_direction: &ray_direction + &self.pixel_delta_v * spacing,
+        };
 
-        Ray::with_time(ray_origin, ray_direction, ray_time)
+        Ray::with_time(ray_origin, ray_direction, ray_time).with_differential(Some(differential))
     }
 
-    fn sample_square() -> Vec3f64 {
-        // Returns the vector to a random point in the [-.5,-.5]-[+.5,+.5] unit square.
-        Vec3f64::new(
-            rand::random_range(-0.5..0.5),
-            rand::random_range(-0.5..0.5),
-            0.0,
-        )
+    fn sample_square(sampler: &mut dyn Sampler) -> Vec3f64 {
+        // Returns the vector to a sampled point in the [-.5,-.5]-[+.5,+.5] unit square.
+        let (u, v) = sampler.get_2d();
+        Vec3f64::new(u - 0.5, v - 0.5, 0.0)
     }
 
-    fn defocus_disk_sample(&self) -> Point {
-        // Returns a random point in the camera defocus disk.
-        let p = Vec3f64::random_in_unit_disk();
+    fn defocus_disk_sample(&self, u: (f64, f64)) -> Point {
+        // Returns the sampled point in the camera defocus disk.
+        let p = Vec3f64::in_unit_disk_from_sample(u);
         &self.center + (&self.defocus_disk_u * p[0]) + (&self.defocus_disk_v * p[1])
     }
 
-    fn ray_color(&self, r: &Ray, depth: i32, world: &dyn Hittable) -> Color {
-        // If we've exceeded the ray bounce limit, no more light is gathered.
-        if depth <= 0 {
-            return Color::zero();
-        }
+    fn ray_color(
+        &self,
+        r: Ray,
+        world: &dyn Hittable,
+        sampler: &mut dyn Sampler,
+        rays: &mut u64,
+        mut guides: Option<&mut Guides>,
+    ) -> Color {
+        // Follows the path bounce by bounce, carrying the product of the attenuations along it
+        // so far, until it escapes the scene, is absorbed, or exceeds the ray bounce limit. The
+        // first hit of the path is added to the guides, if given.
+        let mut radiance = Color::zero();
+        let mut throughput = Color::one();
+        let mut r = r;
+
+        for bounce in 0..self.max_depth {
+            *rays += 1;
+            let Some(rec) = world.hit(&r, Interval::POSITIVE) else {
+                radiance += throughput * self.background_color(&r);
+                break;
+            };
+
+            if bounce == 0
+                && let Some(guides) = &mut guides
+            {
+                guides.albedo += rec.mat.albedo(&rec);
+                guides.normal += &rec.normal;
+                guides.depth += rec.t * r.direction().length();
+            }
 
-        if let Some(rec) = world.hit(r, Interval::from(0.001, f64::INFINITY)) {
-            let color_from_emission = rec.mat.emitted(rec.u, rec.v, &rec.p);
-            if let Some((scattered, attenuation)) = rec.mat.scatter(r, &rec) {
-                let color_from_scatter = attenuation * self.ray_color(&scattered, depth - 1, world);
-                color_from_emission + color_from_scatter
-            } else {
-                color_from_emission
+            radiance += &throughput * rec.mat.emitted(&rec);
+
+            let Some((scattered, attenuation)) = rec.mat.scatter(&r, &rec, sampler) else {
+                break;
+            };
+            throughput = throughput * attenuation;
+
+            // Russian roulette: once past the minimum depth, continue the path with a
+            // probability following its throughput, and divide the surviving paths by that
+            // probability so the estimate stays unbiased.
+            if self.roulette_min_depth.is_some_and(|min| bounce >= min) {
+                let survival = throughput[0]
+                    .max(throughput[1])
+                    .max(throughput[2])
+                    .min(0.95);
+                if sampler.get_1d() >= survival {
+                    break;
+                }
+                throughput /= survival;
             }
+
+            r = scattered;
+        }
+
+        radiance
+    }
+
+    fn background_color(&self, r: &Ray) -> Color {
+        if let Some(sun_light) = self.sunlight_dir.as_ref()
+            && r.direction().unit_vector().dot(sun_light) < -0.99
+        {
+            Color::all(20.0)
         } else {
-            if let Some(sun_light) = self.sunlight_dir.as_ref() {
-                if r.direction().unit_vector().dot(sun_light) < -0.99 {
-                    Color::all(20.0)
-                } else {
-                    self.background.clone()
+            self.background.clone()
+        }
+    }
+}
+
+// First hit albedo, normal and depth of a pixel's samples, summed for the denoiser.
+#[derive(Default)]
+struct Guides {
+    albedo: Color,
+    normal: Vec3f64,
+    depth: f64,
+}
+
+// A horizontal band of the image rendered by a single task. Samples near the top and bottom of
+// the band may splat into the neighbouring bands, so the accumulation buffers cover the band's
+// rows plus the filter's reach on either side and are summed once all bands are done.
+struct Band {
+    rows: Range<usize>,       // Image rows whose pixels are sampled by this band
+    splat_rows: Range<usize>, // Image rows the samples can contribute to
+    width: usize,
+    sum: Vec<Color>,     // Filter-weighted sum of sample radiance over splat_rows
+    weight: Vec<f64>,    // Sum of filter weights over splat_rows
+    average: Vec<Color>, // Unweighted average of each pixel's own samples over rows
+    albedo: Vec<Color>,  // First hit guides over rows (empty when guides are disabled)
+    normal: Vec<Vec3f64>,
+    depth: Vec<f64>,
+    rays: u64, // Rays traced since the last progress update
+}
+
+impl Band {
+    fn new(rows: Range<usize>, reach: usize, width: usize, height: usize, guides: bool) -> Self {
+        let splat_rows = rows.start.saturating_sub(reach)..(rows.end + reach).min(height);
+        let splat_len = splat_rows.len() * width;
+        let row_len = rows.len() * width;
+        let guide_len = if guides { row_len } else { 0 };
+        Self {
+            rows,
+            splat_rows,
+            width,
+            sum: vec![Color::zero(); splat_len],
+            weight: vec![0.0; splat_len],
+            average: vec![Color::zero(); row_len],
+            albedo: vec![Color::zero(); guide_len],
+            normal: vec![Vec3f64::zero(); guide_len],
+            depth: vec![0.0; guide_len],
+            rays: 0,
+        }
+    }
+
+    fn has_guides(&self) -> bool {
+        !self.depth.is_empty()
+    }
+
+    fn splat(&mut self, x: f64, y: f64, color: &Color, filter: &Filter) {
+        // Adds the sample at film position (x, y) to every pixel within the filter's radius.
+        let radius = filter.radius();
+        let x0 = (x - radius).ceil().max(0.0) as usize;
+        let x1 = ((x + radius).floor() as usize).min(self.width - 1);
+        let y0 = ((y - radius).ceil().max(0.0) as usize).max(self.splat_rows.start);
+        let y1 = ((y + radius).floor() as usize).min(self.splat_rows.end - 1);
+
+        for py in y0..=y1 {
+            for px in x0..=x1 {
+                let w = filter.evaluate(px as f64 - x, py as f64 - y);
+                if w != 0.0 {
+                    let k = (py - self.splat_rows.start) * self.width + px;
+                    self.sum[k] += color * w;
+                    self.weight[k] += w;
                 }
-            } else {
-                self.background.clone()
             }
         }
     }
@@ -0,0 +1,195 @@
+use crate::asset::AssetResolver;
+use crate::bvh::BVHOptions;
+use crate::camera::Camera;
+use crate::denoise::{self, Denoiser};
+use crate::filter::Filter;
+use crate::sampler::SamplerKind;
+use std::env;
+use std::process;
+use std::str::FromStr;
+
+const USAGE: &str = "\
+Usage: ray-tracer [SCENE] [OPTIONS]
+
+SCENE is the number of the scene to render (default: 11).
+
+Options:
+  --width <N>                Override the image width of the scene
+  --spp <N>                  Override the samples per pixel of the scene
+  --sampler <NAME>           Sample generator: independent, stratified, halton, sobol or
+                             blue-noise (default: independent)
+  --roulette <N>             Enable Russian roulette path termination after N bounces
+  --clamp <L>                Clamp the radiance of each sample to L to suppress fireflies.
+                             This biases the image (darker highlights), use for previews only
+  --filter <NAME>            Pixel reconstruction filter: box, gaussian, mitchell, lanczos or
+                             blackman-harris (default: box)
+  --filter-radius <R>        Radius of the reconstruction filter in pixels
+  --denoise                  Run the à-trous denoiser on the rendered image
+  --denoise-iterations <N>   Number of wavelet levels of the denoiser, at most 16 (default: 5)
+  --denoise-sigma-color <S>  Color edge-stopping strength of the denoiser (default: 4.0)
+  --gltf <FILE>              glTF file rendered by scene 13 (default: scene.gltf)
+  --assets <DIR>             Look for images, models and scene files in DIR before the default
+                             locations. May be given several times, searched in order
+  --model-cache <DIR>        Keep loaded models with their BVH in DIR, and load them from there
+                             while their file and placement are unchanged
+  --bench                    Benchmark BVH build and rendering of the final scene instead of
+                             rendering SCENE, comparing median split and SAH builders, and
+                             binary and four-wide SAH trees, also on closest-hit and
+                             any-hit queries of shadow rays. Then compare refitting and
+                             rebuilding the BVH of moving spheres, and time the BVH build of
+                             a 2M triangle mesh on one and all threads
+  --bvh-bins <N>             Number of bins of the SAH builder in the benchmark (default: 12)
+  --bvh-leaf-size <N>        Largest leaf of the SAH builder in the benchmark (default: 4)
+  -h, --help                 Print this help";
+
+#[derive(Default)]
+pub struct Options {
+    pub scene: Option<u32>,
+    pub bench: bool,
+    pub bvh: BVHOptions,
+    pub gltf: Option<String>,
+    pub assets: AssetResolver,
+    pub image_width: Option<i32>,
+    pub samples_per_pixel: Option<i32>,
+    pub sampler: Option<SamplerKind>,
+    pub roulette_min_depth: Option<i32>,
+    pub max_sample_radiance: Option<f64>,
+    pub filter: Option<Filter>,
+    pub filter_radius: Option<f64>,
+    pub denoiser: Option<Denoiser>,
+}
+
+impl Options {
+    pub fn from_args() -> Self {
+        match Self::parse(env::args().skip(1)) {
+            Ok(options) => options,
+            Err(msg) => {
+                eprintln!("Error: {msg}\n\n{USAGE}");
+                process::exit(2);
+            }
+        }
+    }
+
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut options = Self::default();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => {
+                    println!("{USAGE}");
+                    process::exit(0);
+                }
+                "--bench" => options.bench = true,
+                "--bvh-bins" => {
+                    options.bvh.bins = Self::value(&arg, args.next())?;
+                    if options.bvh.bins < 2 {
+                        return Err(format!("'{arg}' needs at least 2 bins"));
+                    }
+                }
+                "--bvh-leaf-size" => {
+                    options.bvh.max_leaf_size = Self::value(&arg, args.next())?;
+                    if options.bvh.max_leaf_size == 0 {
+                        return Err(format!("'{arg}' needs a leaf size of at least 1"));
+                    }
+                }
+                "--gltf" => options.gltf = Some(Self::value(&arg, args.next())?),
+                "--assets" => {
+                    let dir: String = Self::value(&arg, args.next())?;
+                    options.assets = options.assets.with_search_dir(dir);
+                }
+                "--model-cache" => {
+                    let dir: String = Self::value(&arg, args.next())?;
+                    options.assets = options.assets.with_cache_dir(dir);
+                }
+                "--width" => {
+                    let width = Self::value(&arg, args.next())?;
+                    if width < 1 {
+                        return Err(format!("'{arg}' needs a width of at least 1"));
+                    }
+                    options.image_width = Some(width);
+                }
+                "--spp" => {
+                    let samples_per_pixel = Self::value(&arg, args.next())?;
+                    if samples_per_pixel < 1 {
+                        return Err(format!("'{arg}' needs at least 1 sample per pixel"));
+                    }
+                    options.samples_per_pixel = Some(samples_per_pixel);
+                }
+                "--sampler" => options.sampler = Some(Self::value(&arg, args.next())?),
+                "--roulette" => options.roulette_min_depth = Some(Self::value(&arg, args.next())?),
+                "--clamp" => {
+                    let max_radiance: f64 = Self::value(&arg, args.next())?;
+                    if !max_radiance.is_finite() || max_radiance <= 0.0 {
+                        return Err(format!("'{arg}' needs a finite radiance above 0"));
+                    }
+                    options.max_sample_radiance = Some(max_radiance);
+                }
+                "--filter" => options.filter = Some(Self::value(&arg, args.next())?),
+                "--filter-radius" => options.filter_radius = Some(Self::value(&arg, args.next())?),
+                "--denoise" => {
+                    options.denoiser.get_or_insert_with(Denoiser::default);
+                }
+                "--denoise-iterations" => {
+                    let denoiser = options.denoiser.get_or_insert_with(Denoiser::default);
+                    denoiser.iterations = Self::value(&arg, args.next())?;
+                    if denoiser.iterations > denoise::MAX_ITERATIONS {
+                        return Err(format!(
+                            "'{arg}' needs at most {} iterations",
+                            denoise::MAX_ITERATIONS
+                        ));
+                    }
+                }
+                "--denoise-sigma-color" => {
+                    let denoiser = options.denoiser.get_or_insert_with(Denoiser::default);
+                    denoiser.sigma_color = Self::value(&arg, args.next())?;
+                }
+                _ if !arg.starts_with('-') && options.scene.is_none() => {
+                    options.scene = Some(Self::value("SCENE", Some(arg.clone()))?);
+                }
+                _ => return Err(format!("unexpected argument '{arg}'")),
+            }
+        }
+
+        // Once the filter is known, as it may come after its radius. A box narrower than half a
+        // pixel would leave pixels without samples.
+        if let Some(radius) = options.filter_radius {
+            if radius.is_nan() || radius <= 0.0 {
+                return Err("'--filter-radius' needs a radius above 0".to_string());
+            }
+            if matches!(options.filter, None | Some(Filter::Box { .. })) && radius < 0.5 {
+                return Err("'--filter-radius' needs a radius of at least 0.5 for box".to_string());
+            }
+        }
+
+        Ok(options)
+    }
+
+    fn value<T: FromStr>(name: &str, value: Option<String>) -> Result<T, String> {
+        let value = value.ok_or_else(|| format!("missing value for '{name}'"))?;
+        value
+            .parse()
+            .map_err(|_| format!("invalid value '{value}' for '{name}'"))
+    }
+
+    pub fn configure(&self, camera: &mut Camera) {
+        // Applies the render settings given on the command line to a scene's camera.
+        if let Some(image_width) = self.image_width {
+            camera.image_width = image_width;
+        }
+        if let Some(samples_per_pixel) = self.samples_per_pixel {
+            camera.samples_per_pixel = samples_per_pixel;
+        }
+        if let Some(sampler) = self.sampler {
+            camera.sampler = sampler;
+        }
+        camera.roulette_min_depth = self.roulette_min_depth;
+        camera.max_sample_radiance = self.max_sample_radiance;
+        if let Some(filter) = &self.filter {
+            camera.filter = filter.clone();
+        }
+        if let Some(radius) = self.filter_radius {
+            camera.filter = camera.filter.clone().with_radius(radius);
+        }
+        camera.denoiser = self.denoiser.clone();
+    }
+}
@@ -1,6 +1,6 @@
 use crate::aabb::AABB;
 use crate::color::Color;
-use crate::hittable::{HitRecord, Hittable};
+use crate::hittable::{Hit, HitRecord, Hittable};
 use crate::interval::Interval;
 use crate::material::{Isotropic, Material};
 use crate::ray::Ray;
@@ -26,32 +26,38 @@ impl Hittable for ConstantMedium {
     // The current implementation assumes that the object's boundary shape
     // is convex, which works for boundaries like boxes or spheres, but
     // not for shapes like tori or those with holes.
-    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
-        let mut rec1 = self.boundary.hit(r, Interval::UNIVERSE)?;
-        let mut rec2 = self
-            .boundary
-            .hit(r, Interval::from(rec1.t + 0.0001, f64::INFINITY))?;
-
-        if rec1.t < ray_t.min {
-            rec1.t = ray_t.min;
-        }
-        if rec2.t > ray_t.max {
-            rec2.t = ray_t.max;
-        }
+    fn closest_hit(&self, r: &Ray, ray_t: Interval) -> Option<Hit> {
+        // The entry point is located on the boundary to start the search for the exit point
+        // just past it.
+        let mut entry = self.boundary.closest_hit(r, Interval::UNIVERSE)?;
+        let entry = self.boundary.surface_point(r, &mut entry);
+        let exit = entry.hit_beyond(self.boundary.as_ref(), r)?;
+
+        let t1 = entry.t.max(ray_t.min);
+        let t2 = exit.t.min(ray_t.max);
 
         let ray_length = r.direction().length();
-        let distance_inside_boundary = (rec2.t - rec1.t) * ray_length;
+        let distance_inside_boundary = (t2 - t1) * ray_length;
         let hit_distance = self.neg_inv_density * rand::random_range(0.0f64..1.0).ln();
 
         if hit_distance > distance_inside_boundary {
             return None;
         }
 
-        let mut rec = rec1;
-        rec.t += hit_distance / ray_length;
-        rec.p = r.at(rec.t);
-        rec.mat = self.phase_function.clone();
-        Some(rec)
+        Some(Hit::new(t1 + hit_distance / ray_length))
+    }
+
+    fn interaction(&self, r: &Ray, hit: &mut Hit) -> HitRecord {
+        // The scattering point is inside the volume rather than on a surface, so it has no
+        // normal, and rays leaving it need no offset. The normal given faces the ray.
+        HitRecord::new(
+            r,
+            hit.t,
+            r.at(hit.t),
+            -r.direction().unit_vector(),
+            self.phase_function.clone(),
+            (0.0, 0.0),
+        )
     }
 
     fn bounding_box(&self) -> &AABB {
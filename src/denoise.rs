@@ -0,0 +1,123 @@
+use crate::color::Color;
+use crate::framebuffer::FrameBuffer;
+use rayon::prelude::*;
+
+// 5-tap B3 spline kernel used by every level of the à-trous wavelet transform.
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+// Most wavelet levels. The taps of the last one are 2^15 pixels apart, wider than any image.
+pub const MAX_ITERATIONS: u32 = 16;
+
+/// Edge-avoiding à-trous wavelet filter (Dammertz et al. 2010).
+///
+/// Each iteration blurs the image with the 5x5 B3 spline kernel, spreading its taps by a factor
+/// of two every pass, while the color, normal, albedo and depth differences between the center
+/// pixel and each tap decide how much that tap may contribute.
+#[derive(Clone, Debug)]
+pub struct Denoiser {
+    pub iterations: u32,   // Number of wavelet levels, each doubles the footprint
+    pub sigma_color: f64,  // Color edge-stopping strength, halved every iteration
+    pub sigma_normal: f64, // Normal edge-stopping strength
+    pub sigma_albedo: f64, // Albedo edge-stopping strength
+    pub sigma_depth: f64,  // Relative depth edge-stopping strength
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Self {
+            iterations: 5,
+            sigma_color: 4.0,
+            sigma_normal: 0.1,
+            sigma_albedo: 0.1,
+            sigma_depth: 0.1,
+        }
+    }
+}
+
+impl Denoiser {
+    pub fn apply(&self, fb: &FrameBuffer) -> Vec<Color> {
+        // Returns the filtered color buffer. The frame buffer must have been rendered with guide
+        // buffers, otherwise the color is returned unchanged.
+
+        if !fb.has_guides() {
+            return fb.color.clone();
+        }
+
+        let mut color = fb.color.clone();
+        let mut sigma_color = self.sigma_color;
+        for level in 0..self.iterations {
+            color = self.filter_level(fb, &color, 1 << level, sigma_color);
+            sigma_color *= 0.5;
+        }
+        color
+    }
+
+    fn filter_level(
+        &self,
+        fb: &FrameBuffer,
+        input: &[Color],
+        step: isize,
+        sigma_color: f64,
+    ) -> Vec<Color> {
+        let width = fb.width();
+        let height = fb.height();
+        let mut output = vec![Color::zero(); input.len()];
+
+        let inv_color = 1.0 / (sigma_color * sigma_color).max(1e-12);
+        let inv_normal = 1.0 / (self.sigma_normal * self.sigma_normal).max(1e-12);
+        let inv_albedo = 1.0 / (self.sigma_albedo * self.sigma_albedo).max(1e-12);
+        let inv_depth = 1.0 / self.sigma_depth.max(1e-12);
+
+        output
+            .par_chunks_mut(width)
+            .enumerate()
+            .for_each(|(j, row)| {
+                for (i, out) in row.iter_mut().enumerate() {
+                    let p = fb.index(i, j);
+                    let c_p = &input[p];
+                    let n_p = &fb.normal[p];
+                    let a_p = &fb.albedo[p];
+                    let d_p = fb.depth[p];
+
+                    let mut sum = Color::zero();
+                    let mut weight_sum = 0.0;
+
+                    for (ky, hy) in KERNEL.iter().enumerate() {
+                        let y = j as isize + (ky as isize - 2) * step;
+                        if y < 0 || y >= height as isize {
+                            continue;
+                        }
+                        for (kx, hx) in KERNEL.iter().enumerate() {
+                            let x = i as isize + (kx as isize - 2) * step;
+                            if x < 0 || x >= width as isize {
+                                continue;
+                            }
+
+                            let q = fb.index(x as usize, y as usize);
+                            let c_q = &input[q];
+
+                            let dist_color = (c_p - c_q).length_squared() * inv_color;
+                            let dist_normal = (n_p - &fb.normal[q]).length_squared() * inv_normal;
+                            let dist_albedo = (a_p - &fb.albedo[q]).length_squared() * inv_albedo;
+
+                            // Compare depths relative to the farther one so the filter behaves the
+                            // same whatever the scene scale.
+                            let d_q = fb.depth[q];
+                            let dist_depth = (d_p - d_q).abs() / d_p.max(d_q).max(1e-8) * inv_depth;
+
+                            let w = hx
+                                * hy
+                                * (-(dist_color + dist_normal + dist_albedo + dist_depth)).exp();
+                            sum += c_q * w;
+                            weight_sum += w;
+                        }
+                    }
+
+                    // The center tap always has a weight of (3/8)^2, so weight_sum is never zero.
+                    *out = sum / weight_sum;
+                }
+            });
+
+        output
+    }
+}
@@ -0,0 +1,120 @@
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+/// Pixel reconstruction filter.
+///
+/// Every camera sample is splatted onto all pixels whose center lies within `radius` (in pixels)
+/// of the sample position, weighted by the filter evaluated at the offset between the two.
+#[derive(Clone, Debug)]
+pub enum Filter {
+    Box { radius: f64 },
+    Gaussian { radius: f64, sigma: f64 },
+    Mitchell { radius: f64, b: f64, c: f64 },
+    Lanczos { radius: f64 },
+    BlackmanHarris { radius: f64 },
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        // A box filter covering exactly one pixel, each sample only counts for its own pixel.
+        Self::Box { radius: 0.5 }
+    }
+}
+
+impl Filter {
+    pub fn radius(&self) -> f64 {
+        match self {
+            Self::Box { radius }
+            | Self::Gaussian { radius, .. }
+            | Self::Mitchell { radius, .. }
+            | Self::Lanczos { radius }
+            | Self::BlackmanHarris { radius } => *radius,
+        }
+    }
+
+    pub fn with_radius(mut self, r: f64) -> Self {
+        match &mut self {
+            Self::Box { radius }
+            | Self::Gaussian { radius, .. }
+            | Self::Mitchell { radius, .. }
+            | Self::Lanczos { radius }
+            | Self::BlackmanHarris { radius } => *radius = r,
+        }
+        self
+    }
+
+    pub fn evaluate(&self, x: f64, y: f64) -> f64 {
+        // Returns the weight of a sample at offset (x, y) from a pixel center. All filters are
+        // separable, so the weight is the product of the 1D filter along both axes.
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+
+    fn evaluate_1d(&self, x: f64) -> f64 {
+        let radius = self.radius();
+        let x = x.abs();
+        if x >= radius {
+            return 0.0;
+        }
+
+        match self {
+            Self::Box { .. } => 1.0,
+            Self::Gaussian { sigma, .. } => {
+                // Subtract the value at the radius so the filter falls off to zero at its edge.
+                let gaussian = |x: f64| (-x * x / (2.0 * sigma * sigma)).exp();
+                (gaussian(x) - gaussian(radius)).max(0.0)
+            }
+            Self::Mitchell { b, c, .. } => {
+                // The Mitchell-Netravali cubic is defined over [-2, 2], so remap the radius.
+                let x = 2.0 * x / radius;
+                if x > 1.0 {
+                    ((-b - 6.0 * c) * x.powi(3)
+                        + (6.0 * b + 30.0 * c) * x * x
+                        + (-12.0 * b - 48.0 * c) * x
+                        + (8.0 * b + 24.0 * c))
+                        / 6.0
+                } else {
+                    ((12.0 - 9.0 * b - 6.0 * c) * x.powi(3)
+                        + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                        + (6.0 - 2.0 * b))
+                        / 6.0
+                }
+            }
+            Self::Lanczos { .. } => Self::sinc(x) * Self::sinc(x / radius),
+            Self::BlackmanHarris { .. } => {
+                // Four-term Blackman-Harris window stretched over [-radius, radius].
+                let t = 2.0 * PI * (x + radius) / (2.0 * radius);
+                0.35875 - 0.48829 * t.cos() + 0.14128 * (2.0 * t).cos() - 0.01168 * (3.0 * t).cos()
+            }
+        }
+    }
+
+    fn sinc(x: f64) -> f64 {
+        if x < 1e-5 {
+            1.0
+        } else {
+            (PI * x).sin() / (PI * x)
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "box" => Ok(Self::default()),
+            "gaussian" => Ok(Self::Gaussian {
+                radius: 1.5,
+                sigma: 0.5,
+            }),
+            "mitchell" => Ok(Self::Mitchell {
+                radius: 2.0,
+                b: 1.0 / 3.0,
+                c: 1.0 / 3.0,
+            }),
+            "lanczos" => Ok(Self::Lanczos { radius: 3.0 }),
+            "blackman-harris" => Ok(Self::BlackmanHarris { radius: 2.0 }),
+            _ => Err(format!("unknown filter '{s}'")),
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use crate::color::{Color, ColorU8};
+use crate::vec3::Vec3f64;
+use image::{ImageBuffer, RgbImage};
+
+#[derive(Default, Clone)]
+pub struct FrameBuffer {
+    width: usize,
+    height: usize,
+    pub color: Vec<Color>,    // Linear radiance of each pixel
+    pub albedo: Vec<Color>,   // First hit albedo guide (empty when guides are disabled)
+    pub normal: Vec<Vec3f64>, // First hit shading normal guide
+    pub depth: Vec<f64>,      // First hit distance guide, 0 for rays that escape the scene
+}
+
+impl FrameBuffer {
+    pub fn new(width: usize, height: usize, with_guides: bool) -> Self {
+        let len = width * height;
+        let guide_len = if with_guides { len } else { 0 };
+        Self {
+            width,
+            height,
+            color: vec![Color::zero(); len],
+            albedo: vec![Color::zero(); guide_len],
+            normal: vec![Vec3f64::zero(); guide_len],
+            depth: vec![0.0; guide_len],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn has_guides(&self) -> bool {
+        !self.depth.is_empty()
+    }
+
+    pub fn index(&self, i: usize, j: usize) -> usize {
+        j * self.width + i
+    }
+
+    pub fn to_image(&self) -> RgbImage {
+        let mut buffer = Vec::with_capacity(self.width * self.height * 3);
+        for color in self.color.iter() {
+            // 把 [0,1] 浮点色值转换到 [0,255]
+            let color: ColorU8 = color.clone().into();
+            buffer.extend_from_slice(&<[u8; 3]>::from(color));
+        }
+        ImageBuffer::from_raw(self.width as u32, self.height as u32, buffer)
+            .expect("from_raw failed: buffer size incorrect")
+    }
+}
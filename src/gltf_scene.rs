@@ -0,0 +1,362 @@
+use crate::asset::{AssetError, AssetKind, AssetResolver};
+use crate::camera::Camera;
+use crate::color::Color;
+use crate::hittable::{Hittable, Instance};
+use crate::hittable_list::HittableList;
+use crate::material::{DiffuseLight, Material, MetallicRoughness};
+use crate::mesh::TriangleMesh;
+use crate::rtweekend::radians_to_degrees;
+use crate::rtwimage::RtwImage;
+use crate::sphere::Sphere;
+use crate::texture::{ImageTexture, NormalMap, ScaledTexture, SolidColor, Texture};
+use crate::transform::Transform;
+use crate::vec3::{Point, Vec3f64};
+use gltf::camera::Projection;
+use gltf::image::Format;
+use gltf::khr_lights_punctual::Kind;
+use std::f64::consts::PI;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// Radius of the emissive spheres standing in for point and spot lights.
+const LIGHT_RADIUS: f64 = 0.05;
+
+/// Scene imported from a glTF 2.0 file (.gltf or .glb).
+///
+/// Every node of the default scene carrying a mesh becomes an instance of that mesh, placed by
+/// the node's world transform, so meshes used by several nodes are only built once. Cameras are
+/// returned with their placement and field of view set, ready for the remaining settings.
+/// Point and spot lights become small emissive spheres, and a directional light becomes the
+/// sunlight direction of the scene, to be set on the camera rendering it.
+///
+/// Buffers and images are only read from the file itself, data URIs and local files next to it.
+pub struct GltfScene {
+    pub world: HittableList,
+    pub cameras: Vec<Camera>,
+    pub has_lights: bool, // Whether the scene has punctual lights or emissive materials
+    pub sunlight_dir: Option<Vec3f64>, // Direction of the directional light, if any
+}
+
+// Data shared by all nodes of the scene during the import.
+struct Context {
+    path: PathBuf, // The glTF file, for error reports
+    buffers: Vec<gltf::buffer::Data>,
+    images: Vec<Option<Arc<RtwImage>>>, // Converted images, shared by all textures using them
+    meshes: Vec<Option<Arc<dyn Hittable>>>, // Meshes already built, by glTF mesh index
+}
+
+impl GltfScene {
+    pub fn load(filename: &str, assets: &AssetResolver) -> Result<Self, AssetError> {
+        // glTF files are looked up like model files. Buffers and images are resolved by the
+        // importer, relative to the file. A file without a scene, or with only cameras and
+        // lights, has nothing to render and is reported as empty.
+        let path = assets.resolve(filename, AssetKind::Model, None)?;
+        let (document, buffers, images) =
+            gltf::import(&path).map_err(|e| AssetError::parse(&path, e))?;
+
+        let mut scene = Self {
+            world: HittableList::default(),
+            cameras: Vec::new(),
+            has_lights: false,
+            sunlight_dir: None,
+        };
+        let mut context = Context {
+            path: path.clone(),
+            buffers,
+            images: images.iter().map(Self::image).collect(),
+            meshes: vec![None; document.meshes().len()],
+        };
+
+        if let Some(root) = document.default_scene().or(document.scenes().next()) {
+            for node in root.nodes() {
+                scene.add_node(&node, &Transform::identity(), &mut context)?;
+            }
+        }
+
+        if scene.world.objects.is_empty() {
+            return Err(AssetError::EmptyScene { path });
+        }
+        Ok(scene)
+    }
+
+    fn add_node(
+        &mut self,
+        node: &gltf::Node,
+        parent: &Transform,
+        context: &mut Context,
+    ) -> Result<(), AssetError> {
+        let transform = Self::node_transform(node).then(parent);
+
+        if let Some(mesh) = node.mesh() {
+            let object = match &context.meshes[mesh.index()] {
+                Some(object) => Some(object.clone()),
+                None => {
+                    let object = self.build_mesh(&mesh, context)?;
+                    context.meshes[mesh.index()] = object.clone();
+                    object
+                }
+            };
+            if let Some(object) = object {
+                self.world
+                    .add(Arc::new(Instance::new(object, transform.clone())));
+            }
+        }
+
+        if let Some(camera) = node.camera() {
+            match camera.projection() {
+                Projection::Perspective(perspective) => {
+                    // glTF cameras look down their local -Z axis, with +Y up.
+                    let mut c = Camera::default();
+                    c.vfov = radians_to_degrees(perspective.yfov() as f64);
+                    // The aspect ratio is optional, without it the camera gets the 16:9 of the
+                    // camera made up for files without one.
+                    c.aspect_ratio = perspective.aspect_ratio().map_or(16.0 / 9.0, f64::from);
+                    c.lookfrom = transform.point(&Point::zero());
+                    c.lookat = &c.lookfrom + transform.vector(&Vec3f64::new(0.0, 0.0, -1.0));
+                    c.vup = transform.vector(&Vec3f64::new(0.0, 1.0, 0.0));
+                    c.defocus_angle = 0.0;
+                    self.cameras.push(c);
+                }
+                Projection::Orthographic(_) => {
+                    eprintln!("Orthographic cameras are not supported, skipping camera.");
+                }
+            }
+        }
+
+        if let Some(light) = node.light() {
+            self.add_light(&light, &transform);
+        }
+
+        for child in node.children() {
+            self.add_node(&child, &transform, context)?;
+        }
+
+        Ok(())
+    }
+
+    fn node_transform(node: &gltf::Node) -> Transform {
+        // glTF matrices are column-major 4x4 matrices.
+        let columns = node.transform().matrix();
+        let mut m = [[0.0; 4]; 3];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, x) in row.iter_mut().enumerate() {
+                *x = columns[j][i] as f64;
+            }
+        }
+        Transform::from_matrix(m)
+    }
+
+    fn add_light(&mut self, light: &gltf::khr_lights_punctual::Light, transform: &Transform) {
+        let color = light.color().map(|c| c as f64);
+        let color = Color::new(color[0], color[1], color[2]);
+
+        match light.kind() {
+            Kind::Directional => {
+                // The light travels along the local -Z axis. The renderer's sunlight has a fixed
+                // intensity and color, only the direction is taken.
+                self.sunlight_dir = Some(transform.vector(&Vec3f64::new(0.0, 0.0, -1.0)));
+            }
+            kind => {
+                if let Kind::Spot { .. } = kind {
+                    eprintln!("Spot light cones are not supported, emitting in all directions.");
+                }
+
+                // A sphere of radius r and radiance L has a radiant intensity of L π r² in every
+                // direction, which matches the light's intensity (in candela) for L = I / (π r²).
+                let radiance =
+                    color * (light.intensity() as f64 / (PI * LIGHT_RADIUS * LIGHT_RADIUS));
+                let mat = Arc::new(DiffuseLight::from(radiance));
+                let center = transform.point(&Point::zero());
+                self.world
+                    .add(Arc::new(Sphere::new(center, LIGHT_RADIUS, mat)));
+                self.has_lights = true;
+            }
+        }
+    }
+
+    fn build_mesh(
+        &mut self,
+        mesh: &gltf::Mesh,
+        context: &Context,
+    ) -> Result<Option<Arc<dyn Hittable>>, AssetError> {
+        // All triangle primitives of the mesh are merged into a single TriangleMesh. Each
+        // primitive adds its material to the mesh's material table. Indices past the vertices
+        // of their primitive make the file invalid.
+        let mut positions = Vec::new();
+        let mut normals = Vec::new();
+        let mut uvs = Vec::new();
+        let mut triangles = Vec::new();
+        let mut materials: Vec<Arc<dyn Material>> = Vec::new();
+        let mut normal_maps = Vec::new();
+        let mut material_ids = Vec::new();
+
+        let has_normals = mesh
+            .primitives()
+            .any(|p| p.get(&gltf::Semantic::Normals).is_some());
+        let has_uvs = mesh
+            .primitives()
+            .any(|p| p.get(&gltf::Semantic::TexCoords(0)).is_some());
+
+        for primitive in mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                eprintln!("Only triangle primitives are supported, skipping primitive.");
+                continue;
+            }
+
+            let reader = primitive.reader(|buffer| Some(&context.buffers[buffer.index()]));
+            let Some(primitive_positions) = reader.read_positions() else {
+                continue;
+            };
+
+            let offset = positions.len() as u32;
+            positions.extend(
+                primitive_positions.map(|p| Point::new(p[0] as f64, p[1] as f64, p[2] as f64)),
+            );
+
+            if has_normals {
+                match reader.read_normals() {
+                    Some(n) => normals
+                        .extend(n.map(|n| Vec3f64::new(n[0] as f64, n[1] as f64, n[2] as f64))),
+                    None => normals.resize(positions.len(), Vec3f64::zero()),
+                }
+            }
+
+            // glTF texture coordinates start at the top left of the image, flip v to match
+            // the texture lookup.
+            if has_uvs {
+                match reader.read_tex_coords(0) {
+                    Some(uv) => {
+                        uvs.extend(uv.into_f32().map(|uv| (uv[0] as f64, 1.0 - uv[1] as f64)))
+                    }
+                    None => uvs.resize(positions.len(), (0.0, 0.0)),
+                }
+            }
+
+            let vertex_count = positions.len() as u32 - offset;
+            let indices: Vec<u32> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..vertex_count).collect(),
+            };
+            if let Some(index) = indices.iter().find(|&&i| i >= vertex_count) {
+                return Err(AssetError::parse(
+                    &context.path,
+                    format!(
+                        "vertex index {index} past the {vertex_count} vertices of its primitive"
+                    ),
+                ));
+            }
+            let triangle_count = indices.len() / 3;
+            triangles.extend(
+                indices
+                    .chunks_exact(3)
+                    .map(|f| [f[0] + offset, f[1] + offset, f[2] + offset]),
+            );
+
+            let (mat, normal_map) = self.convert_material(&primitive.material(), context);
+            material_ids.resize(material_ids.len() + triangle_count, materials.len() as u32);
+            materials.push(mat);
+            normal_maps.push(normal_map);
+        }
+
+        if triangles.is_empty() {
+            return Ok(None);
+        }
+
+        let mesh = TriangleMesh::new(positions, normals, uvs, triangles, materials, material_ids)
+            .with_normal_maps(normal_maps);
+        Ok(Some(Arc::new(mesh)))
+    }
+
+    fn convert_material(
+        &mut self,
+        material: &gltf::Material,
+        context: &Context,
+    ) -> (Arc<dyn Material>, Option<Arc<NormalMap>>) {
+        // Texture factors scale the texel values, or stand alone without a texture.
+        let factor_texture = |factor: Color, texture: Option<gltf::texture::Texture>, linear| {
+            let image = texture.and_then(|t| context.images[t.source().index()].clone());
+            let tex: Arc<dyn Texture> = match image {
+                Some(image) if linear => Arc::new(ScaledTexture::new(
+                    Arc::new(ImageTexture::from_linear_image(image)),
+                    factor,
+                )),
+                Some(image) => Arc::new(ScaledTexture::new(
+                    Arc::new(ImageTexture::from_image(image)),
+                    factor,
+                )),
+                None => Arc::new(SolidColor::from(factor)),
+            };
+            tex
+        };
+
+        let pbr = material.pbr_metallic_roughness();
+        let [r, g, b, _] = pbr.base_color_factor().map(|c| c as f64);
+        let base_color = factor_texture(
+            Color::new(r, g, b),
+            pbr.base_color_texture().map(|info| info.texture()),
+            false,
+        );
+
+        let metallic_roughness = factor_texture(
+            Color::new(
+                1.0,
+                pbr.roughness_factor() as f64,
+                pbr.metallic_factor() as f64,
+            ),
+            pbr.metallic_roughness_texture().map(|info| info.texture()),
+            true,
+        );
+
+        let strength = material.emissive_strength().unwrap_or(1.0) as f64;
+        let [r, g, b] = material.emissive_factor().map(|c| c as f64 * strength);
+        if r > 0.0 || g > 0.0 || b > 0.0 {
+            self.has_lights = true;
+        }
+        let emission = factor_texture(
+            Color::new(r, g, b),
+            material.emissive_texture().map(|info| info.texture()),
+            false,
+        );
+
+        let normal_map = material.normal_texture().and_then(|normal| {
+            let image = context.images[normal.texture().source().index()].clone()?;
+            Some(Arc::new(NormalMap::from_image(image)))
+        });
+
+        let mat = Arc::new(MetallicRoughness::new(
+            base_color,
+            metallic_roughness,
+            emission,
+        ));
+        (mat, normal_map)
+    }
+
+    fn image(data: &gltf::image::Data) -> Option<Arc<RtwImage>> {
+        // Converts a decoded glTF image to 8-bit RGB, dropping alpha. Single and two channel
+        // images are expanded to gray.
+        let channels = match data.format {
+            Format::R8 => 1,
+            Format::R8G8 => 2,
+            Format::R8G8B8 => 3,
+            Format::R8G8B8A8 => 4,
+            format => {
+                eprintln!("Unsupported glTF image format {format:?}, ignoring texture.");
+                return None;
+            }
+        };
+
+        let bdata = data
+            .pixels
+            .chunks_exact(channels)
+            .flat_map(|p| match channels {
+                1 | 2 => [p[0], p[0], p[0]],
+                _ => [p[0], p[1], p[2]],
+            })
+            .collect();
+        Some(Arc::new(RtwImage::from_rgb8(
+            data.width,
+            data.height,
+            bdata,
+        )))
+    }
+}
@@ -1,19 +1,83 @@
 use crate::aabb::AABB;
+use crate::color::Color;
 use crate::interval::Interval;
 use crate::material::Material;
-use crate::ray::Ray;
-use crate::rtweekend::degrees_to_radians;
+use crate::ray::{Ray, RayDifferential};
+use crate::rtweekend::{degrees_to_radians, gamma};
+use crate::texture::UVDerivatives;
+use crate::transform::Transform;
 use crate::vec3::{Point, Vec3f64};
 use std::sync::Arc;
 
+// Nesting depth of objects whose hit path is stored without allocating.
+const INLINE_HIT_DEPTH: usize = 8;
+
+/// Closest hit found while searching a ray, before any shading data is computed.
+///
+/// Only the ray parameter, the coordinates of the hit on its primitive (barycentric coordinates
+/// of a triangle, plane coordinates of a quad) and the path of child indices leading to the
+/// primitive are kept, so candidates replaced by closer hits cost little. The containers on the
+/// way push the index of their child as the hit comes back up, and `Hittable::interaction` pops
+/// them on its way back down to the primitive. Paths deeper than a few levels spill to the heap.
+#[derive(Clone)]
+pub struct Hit {
+    pub t: f64,
+    pub coords: (f64, f64),
+    path: [u32; INLINE_HIT_DEPTH],
+    spill: Vec<u32>, // Indices pushed past the inline ones
+    depth: usize,
+}
+
+impl Hit {
+    pub fn new(t: f64) -> Self {
+        Self::with_coords(t, (0.0, 0.0))
+    }
+
+    pub fn with_coords(t: f64, coords: (f64, f64)) -> Self {
+        Self {
+            t,
+            coords,
+            path: [0; INLINE_HIT_DEPTH],
+            spill: Vec::new(),
+            depth: 0,
+        }
+    }
+
+    pub fn push(&mut self, index: usize) {
+        if self.depth < INLINE_HIT_DEPTH {
+            self.path[self.depth] = index as u32;
+        } else {
+            self.spill.push(index as u32);
+        }
+        self.depth += 1;
+    }
+
+    pub fn pop(&mut self) -> usize {
+        self.depth -= 1;
+        if self.depth < INLINE_HIT_DEPTH {
+            self.path[self.depth] as usize
+        } else {
+            self.spill.pop().unwrap() as usize
+        }
+    }
+}
+
 pub struct HitRecord {
     pub t: f64,
     pub p: Point,
+    pub p_error: Vec3f64, // Bound on the rounding error of each coordinate of p
     pub front_face: bool,
-    pub normal: Vec3f64,
+    pub normal: Vec3f64, // Shading normal, on the same side as the incoming ray
+    pub geometric_normal: Vec3f64, // Normal of the actual surface, on the same side as normal
     pub mat: Arc<dyn Material>,
     pub u: f64,
     pub v: f64,
+    pub dpdu: Vec3f64, // Rate of change of p with u, zero if the surface has no parametrization
+    pub dpdv: Vec3f64, // Rate of change of p with v
+    pub dpdx: Vec3f64, // Change of p to the next pixel along x, zero without differentials
+    pub dpdy: Vec3f64, // Change of p to the next pixel along y
+    pub uv_derivatives: UVDerivatives, // Footprint of the pixel in texture space
+    pub color: Color,  // Vertex color at the hit point, scales the surface color of the material
 }
 
 impl HitRecord {
@@ -34,17 +98,194 @@ impl HitRecord {
         Self {
             t,
             p,
+            p_error: Vec3f64::zero(),
             front_face,
+            geometric_normal: normal.clone(),
             normal,
             mat,
             u: uv.0,
             v: uv.1,
+            dpdu: Vec3f64::zero(),
+            dpdv: Vec3f64::zero(),
+            dpdx: Vec3f64::zero(),
+            dpdy: Vec3f64::zero(),
+            uv_derivatives: UVDerivatives::default(),
+            color: Color::one(),
         }
     }
+
+    pub fn with_shading_normal(mut self, outward_normal: Vec3f64) -> Self {
+        // Replaces the shading normal, e.g. with one interpolated from vertex normals, keeping
+        // the geometric normal for deciding which side of the surface was hit.
+        self.normal = if self.front_face {
+            outward_normal
+        } else {
+            -outward_normal
+        };
+        self
+    }
+
+    pub fn with_p_error(mut self, p_error: Vec3f64) -> Self {
+        self.p_error = p_error;
+        self
+    }
+
+    pub fn with_dpduv(mut self, dpdu: Vec3f64, dpdv: Vec3f64) -> Self {
+        self.dpdu = dpdu;
+        self.dpdv = dpdv;
+        self
+    }
+
+    pub fn with_ray_differential(mut self, r: &Ray) -> Self {
+        // Finds where the offset rays of r meet the tangent plane at p, which gives the change
+        // of p from one pixel to the next. Projecting that change onto dpdu and dpdv (by least
+        // squares, as it needn't lie exactly in their span) gives the change of the texture
+        // coordinates.
+        let Some(d) = r.differential() else {
+            return self;
+        };
+        let n = &self.geometric_normal;
+        let plane = n.dot(&self.p);
+        let tx = (plane - n.dot(&d.rx_origin)) / n.dot(&d.rx_direction);
+        let ty = (plane - n.dot(&d.ry_origin)) / n.dot(&d.ry_direction);
+        if !tx.is_finite() || !ty.is_finite() {
+            return self;
+        }
+        self.dpdx = &d.rx_origin + &(&d.rx_direction * tx) - &self.p;
+        self.dpdy = &d.ry_origin + &(&d.ry_direction * ty) - &self.p;
+
+        let ata00 = self.dpdu.dot(&self.dpdu);
+        let ata01 = self.dpdu.dot(&self.dpdv);
+        let ata11 = self.dpdv.dot(&self.dpdv);
+        let inv_det = 1.0 / (ata00 * ata11 - ata01 * ata01);
+        if !inv_det.is_finite() {
+            return self;
+        }
+        let solve = |dp: &Vec3f64| {
+            let (atb0, atb1) = (self.dpdu.dot(dp), self.dpdv.dot(dp));
+            (
+                (ata11 * atb0 - ata01 * atb1) * inv_det,
+                (ata00 * atb1 - ata01 * atb0) * inv_det,
+            )
+        };
+        let (dudx, dvdx) = solve(&self.dpdx);
+        let (dudy, dvdy) = solve(&self.dpdy);
+        self.uv_derivatives = UVDerivatives {
+            dudx,
+            dvdx,
+            dudy,
+            dvdy,
+        };
+        self
+    }
+
+    pub fn spawn_differential(
+        &self,
+        r_in: &Ray,
+        scatter: impl Fn(&Vec3f64) -> Option<Vec3f64>,
+    ) -> Option<RayDifferential> {
+        // Differentials of a ray leaving p along a specular direction: the offset rays start
+        // from the neighbouring points on the surface and scatter their own directions the same
+        // way. The normal is taken as constant over the footprint, which underestimates the
+        // spread after curved mirrors. Returns None if r_in has no differentials or an offset
+        // ray doesn't scatter, e.g. from total internal reflection.
+        let d = r_in.differential()?;
+        Some(RayDifferential {
+            rx_origin: &self.p + &self.dpdx,
+            rx_direction: scatter(&d.rx_direction)?,
+            ry_origin: &self.p + &self.dpdy,
+            ry_direction: scatter(&d.ry_direction)?,
+        })
+    }
+
+    pub fn spawn_ray(&self, direction: Vec3f64, time: f64) -> Ray {
+        let origin = offset_origin(&self.p, &self.p_error, &self.geometric_normal, &direction);
+        Ray::with_time(origin, direction, time)
+    }
+}
+
+/// Location of a hit on a surface, without its shading data.
+///
+/// It holds what is needed to start a ray from the surface, e.g. to find where a ray that
+/// entered a volume leaves it, for a fraction of the cost of a `HitRecord`.
+pub struct SurfacePoint {
+    pub t: f64,
+    pub p: Point,
+    pub p_error: Vec3f64, // Bound on the rounding error of each coordinate of p
+    pub normal: Vec3f64,  // Unit geometric normal, on either side of the surface
+}
+
+impl SurfacePoint {
+    pub fn spawn_ray(&self, direction: Vec3f64, time: f64) -> Ray {
+        let origin = offset_origin(&self.p, &self.p_error, &self.normal, &direction);
+        Ray::with_time(origin, direction, time)
+    }
+
+    pub fn hit_beyond(&self, object: &dyn Hittable, r: &Ray) -> Option<Hit> {
+        // Finds the next hit of object along r after this one, such as the point where r leaves
+        // a convex object it entered here. The returned t is along r.
+        let next = self.spawn_ray(r.direction().clone(), r.time());
+        let mut hit = object.closest_hit(&next, Interval::POSITIVE)?;
+        hit.t += self.t;
+        Some(hit)
+    }
+}
+
+fn offset_origin(p: &Point, p_error: &Vec3f64, normal: &Vec3f64, direction: &Vec3f64) -> Point {
+    // Starts a ray at the hit point p, moved along the geometric normal just past the error
+    // bounds of p on the side the ray leaves towards. The computed origin is then on the right
+    // side of the surface, so the ray can't hit it again right away, and rays are traced from
+    // t = 0 rather than from some scene-dependent epsilon.
+    let n = if direction.dot(normal) < 0.0 {
+        -normal.clone()
+    } else {
+        normal.clone()
+    };
+    let offset = &n * n.abs().dot(p_error);
+
+    // Adding the offset rounds too, so the origin is moved one more ulp away from p. This also
+    // moves points known exactly, such as those on axis-aligned quads, off the surface.
+    let mut origin = p + &offset;
+    for i in 0..3 {
+        if n[i] > 0.0 {
+            origin[i] = origin[i].next_up();
+        } else if n[i] < 0.0 {
+            origin[i] = origin[i].next_down();
+        }
+    }
+    origin
 }
 
 pub trait Hittable: Send + Sync {
-    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord>;
+    // Finds the closest hit of the ray within ray_t, without computing its shading data.
+    fn closest_hit(&self, r: &Ray, ray_t: Interval) -> Option<Hit>;
+
+    // Builds the full record of a hit returned by closest_hit for the same ray, popping the
+    // indices this object pushed on its path.
+    fn interaction(&self, r: &Ray, hit: &mut Hit) -> HitRecord;
+
+    // Locates a hit returned by closest_hit on the surface, like interaction without the
+    // shading data. Objects with cheaper ways to find the point override it.
+    fn surface_point(&self, r: &Ray, hit: &mut Hit) -> SurfacePoint {
+        let rec = self.interaction(r, hit);
+        SurfacePoint {
+            t: rec.t,
+            p: rec.p,
+            p_error: rec.p_error,
+            normal: rec.geometric_normal,
+        }
+    }
+
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let mut hit = self.closest_hit(r, ray_t)?;
+        Some(self.interaction(r, &mut hit).with_ray_differential(r))
+    }
+
+    // Whether the ray hits anything within ray_t, e.g. between a point and a light. Unlike
+    // hit, it can stop at the first hit found and doesn't need to build a hit record.
+    fn occluded(&self, r: &Ray, ray_t: Interval) -> bool {
+        self.closest_hit(r, ray_t).is_some()
+    }
 
     fn bounding_box(&self) -> &AABB;
 }
@@ -64,20 +305,38 @@ impl Translate {
             bbox,
         }
     }
+
+    fn object_ray(&self, r: &Ray) -> Ray {
+        Ray::with_time(r.origin() - &self.offset, r.direction().clone(), r.time())
+    }
 }
 
 impl Hittable for Translate {
-    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
-        // Move the ray backwards by the offset
-        let offset_r = Ray::with_time(r.origin() - &self.offset, r.direction().clone(), r.time());
+    fn closest_hit(&self, r: &Ray, ray_t: Interval) -> Option<Hit> {
+        // Move the ray backwards by the offset, and determine whether an intersection exists
+        // along the offset ray (and if so, where)
+        self.object.closest_hit(&self.object_ray(r), ray_t)
+    }
 
-        // Determine whether an intersection exists along the offset ray (and if so, where)
-        let mut rec = self.object.hit(&offset_r, ray_t)?;
+    fn interaction(&self, r: &Ray, hit: &mut Hit) -> HitRecord {
+        let mut rec = self.object.interaction(&self.object_ray(r), hit);
 
         // Move the intersection point forwards by the offset
         rec.p += &self.offset;
+        rec.p_error += rec.p.abs() * gamma(1);
 
-        Some(rec)
+        rec
+    }
+
+    fn surface_point(&self, r: &Ray, hit: &mut Hit) -> SurfacePoint {
+        let mut point = self.object.surface_point(&self.object_ray(r), hit);
+        point.p += &self.offset;
+        point.p_error += point.p.abs() * gamma(1);
+        point
+    }
+
+    fn occluded(&self, r: &Ray, ray_t: Interval) -> bool {
+        self.object.occluded(&self.object_ray(r), ray_t)
     }
 
     fn bounding_box(&self) -> &AABB {
@@ -113,6 +372,14 @@ impl RotateY {
         )
     }
 
+    fn object_ray(&self, r: &Ray) -> Ray {
+        Ray::with_time(
+            self.transform(r.origin()),
+            self.transform(r.direction()),
+            r.time(),
+        )
+    }
+
     fn transform_back(&self, v: &Vec3f64) -> Vec3f64 {
         Point::new(
             (self.cos_theta * v.x()) + (self.sin_theta * v.z()),
@@ -120,23 +387,140 @@ impl RotateY {
             (-self.sin_theta * v.x()) + (self.cos_theta * v.z()),
         )
     }
+
+    fn transform_back_error(&self, p: &Point, p_error: &Vec3f64) -> Vec3f64 {
+        // Error bound of transform_back(p), given the error of p: the rotation carries the
+        // error of p over and adds its own rounding.
+        let (sin_theta, cos_theta) = (self.sin_theta.abs(), self.cos_theta.abs());
+        let bound = |v: &Vec3f64| {
+            Vec3f64::new(
+                cos_theta * v.x() + sin_theta * v.z(),
+                *v.y(),
+                sin_theta * v.x() + cos_theta * v.z(),
+            )
+        };
+        bound(p_error) * (1.0 + gamma(3)) + bound(&p.abs()) * gamma(3)
+    }
 }
 
 impl Hittable for RotateY {
-    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
-        // Transform the ray from world space to object space.
-        let origin = self.transform(r.origin());
-        let direction = self.transform(r.direction());
-        let rotated_r = Ray::with_time(origin, direction, r.time());
+    fn closest_hit(&self, r: &Ray, ray_t: Interval) -> Option<Hit> {
+        // Transform the ray from world space to object space, and determine whether an
+        // intersection exists in object space (and if so, where).
+        self.object.closest_hit(&self.object_ray(r), ray_t)
+    }
 
-        // Determine whether an intersection exists in object space (and if so, where).
-        let mut rec = self.object.hit(&rotated_r, ray_t)?;
+    fn interaction(&self, r: &Ray, hit: &mut Hit) -> HitRecord {
+        let mut rec = self.object.interaction(&self.object_ray(r), hit);
 
         // Transform the intersection from object space back to world space.
+        rec.p_error = self.transform_back_error(&rec.p, &rec.p_error);
         rec.p = self.transform_back(&rec.p);
         rec.normal = self.transform_back(&rec.normal);
+        rec.geometric_normal = self.transform_back(&rec.geometric_normal);
+        rec.dpdu = self.transform_back(&rec.dpdu);
+        rec.dpdv = self.transform_back(&rec.dpdv);
+
+        rec
+    }
+
+    fn surface_point(&self, r: &Ray, hit: &mut Hit) -> SurfacePoint {
+        let mut point = self.object.surface_point(&self.object_ray(r), hit);
+        point.p_error = self.transform_back_error(&point.p, &point.p_error);
+        point.p = self.transform_back(&point.p);
+        point.normal = self.transform_back(&point.normal);
+        point
+    }
+
+    fn occluded(&self, r: &Ray, ray_t: Interval) -> bool {
+        self.object.occluded(&self.object_ray(r), ray_t)
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        &self.bbox
+    }
+}
+
+/// Placement of shared geometry in the scene.
+///
+/// Many instances can reference the same object (a mesh, or a whole sub-scene BVH) so that it is
+/// only stored and built once. Each instance has its own transform and can override the material
+/// of the object. A BVH built over the instances forms the top level of the scene.
+pub struct Instance {
+    object: Arc<dyn Hittable>,
+    transform: Transform,
+    mat: Option<Arc<dyn Material>>,
+    bbox: AABB,
+}
+
+impl Instance {
+    pub fn new(object: Arc<dyn Hittable>, transform: Transform) -> Self {
+        let bbox = transform.bbox(object.bounding_box());
+        Self {
+            object,
+            transform,
+            mat: None,
+            bbox,
+        }
+    }
+
+    pub fn with_mat(
+        object: Arc<dyn Hittable>,
+        transform: Transform,
+        mat: Arc<dyn Material>,
+    ) -> Self {
+        Self {
+            mat: Some(mat),
+            ..Self::new(object, transform)
+        }
+    }
+
+    fn object_ray(&self, r: &Ray) -> Ray {
+        // Transforms the ray into object space. The direction is not normalized, so the ray
+        // parameter t is the same in both spaces.
+        Ray::with_time(
+            self.transform.inverse_point(r.origin()),
+            self.transform.inverse_vector(r.direction()),
+            r.time(),
+        )
+    }
+}
+
+impl Hittable for Instance {
+    fn closest_hit(&self, r: &Ray, ray_t: Interval) -> Option<Hit> {
+        self.object.closest_hit(&self.object_ray(r), ray_t)
+    }
+
+    fn interaction(&self, r: &Ray, hit: &mut Hit) -> HitRecord {
+        let mut rec = self.object.interaction(&self.object_ray(r), hit);
+
+        // Transform the intersection back to world space.
+        rec.p_error = self.transform.point_error(&rec.p, &rec.p_error);
+        rec.p = self.transform.point(&rec.p);
+        rec.normal = self.transform.normal(&rec.normal).into_unit_vector();
+        rec.geometric_normal = self
+            .transform
+            .normal(&rec.geometric_normal)
+            .into_unit_vector();
+        rec.dpdu = self.transform.vector(&rec.dpdu);
+        rec.dpdv = self.transform.vector(&rec.dpdv);
+        if let Some(mat) = &self.mat {
+            rec.mat = mat.clone();
+        }
+
+        rec
+    }
+
+    fn surface_point(&self, r: &Ray, hit: &mut Hit) -> SurfacePoint {
+        let mut point = self.object.surface_point(&self.object_ray(r), hit);
+        point.p_error = self.transform.point_error(&point.p, &point.p_error);
+        point.p = self.transform.point(&point.p);
+        point.normal = self.transform.normal(&point.normal).into_unit_vector();
+        point
+    }
 
-        Some(rec)
+    fn occluded(&self, r: &Ray, ray_t: Interval) -> bool {
+        self.object.occluded(&self.object_ray(r), ray_t)
     }
 
     fn bounding_box(&self) -> &AABB {
@@ -1,5 +1,5 @@
 use crate::aabb::AABB;
-use crate::hittable::{HitRecord, Hittable};
+use crate::hittable::{Hit, HitRecord, Hittable, SurfacePoint};
 use crate::interval::Interval;
 use crate::ray::Ray;
 use std::sync::Arc;
@@ -24,18 +24,32 @@ impl HittableList {
 }
 
 impl Hittable for HittableList {
-    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
-        let mut result_rec = None;
+    fn closest_hit(&self, r: &Ray, ray_t: Interval) -> Option<Hit> {
+        let mut result = None;
         let mut closest_so_far = ray_t.max;
 
-        for object in self.objects.iter() {
-            if let Some(rec) = object.hit(r, Interval::from(ray_t.min, closest_so_far)) {
-                closest_so_far = rec.t;
-                result_rec = Some(rec);
+        for (i, object) in self.objects.iter().enumerate() {
+            if let Some(mut hit) = object.closest_hit(r, Interval::from(ray_t.min, closest_so_far))
+            {
+                closest_so_far = hit.t;
+                hit.push(i);
+                result = Some(hit);
             };
         }
 
-        result_rec
+        result
+    }
+
+    fn interaction(&self, r: &Ray, hit: &mut Hit) -> HitRecord {
+        self.objects[hit.pop()].interaction(r, hit)
+    }
+
+    fn surface_point(&self, r: &Ray, hit: &mut Hit) -> SurfacePoint {
+        self.objects[hit.pop()].surface_point(r, hit)
+    }
+
+    fn occluded(&self, r: &Ray, ray_t: Interval) -> bool {
+        self.objects.iter().any(|object| object.occluded(r, ray_t))
     }
 
     fn bounding_box(&self) -> &AABB {
@@ -10,6 +10,7 @@ impl Interval {
     pub const EMPTY: Interval = Interval::from(f64::INFINITY, -f64::INFINITY);
     pub const UNIVERSE: Interval = Interval::from(-f64::INFINITY, f64::INFINITY);
     pub const I01: Interval = Interval::from(0.001, 0.999);
+    pub const POSITIVE: Interval = Interval::from(0.0, f64::INFINITY);
 
     pub const fn new() -> Self {
         Self::EMPTY
@@ -48,8 +49,8 @@ impl Interval {
 
     pub fn into_expand(mut self, delta: f64) -> Self {
         let padding = delta / 2.0;
-        self.min = self.min - padding;
-        self.max = self.max + padding;
+        self.min -= padding;
+        self.max += padding;
         self
     }
 }
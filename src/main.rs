@@ -1,40 +1,63 @@
 mod aabb;
+mod asset;
 mod bvh;
+mod cache;
 mod camera;
+mod cli;
 mod color;
 mod constant_medium;
+mod denoise;
+mod filter;
+mod framebuffer;
+mod gltf_scene;
 mod hittable;
 mod hittable_list;
 mod interval;
 mod material;
+mod mesh;
 mod model;
 mod perlin;
+mod ply;
 mod quad;
 mod ray;
 mod rtweekend;
 mod rtwimage;
+mod sampler;
 mod sphere;
 mod texture;
+mod transform;
+mod triangle;
 mod vec3;
+mod wide_bvh;
 
-use crate::bvh::BVHNode;
+use crate::asset::{AssetError, AssetResolver};
+use crate::bvh::{BVHNode, BVHOptions, SplitMethod};
 use crate::camera::Camera;
+use crate::cli::Options;
 use crate::color::Color;
 use crate::constant_medium::ConstantMedium;
-use crate::hittable::{Hittable, RotateY, Translate};
+use crate::gltf_scene::GltfScene;
+use crate::hittable::{Hittable, Instance, RotateY, Translate};
 use crate::hittable_list::HittableList;
-use crate::material::{Dielectric, DiffuseLight, Lambertian, Metal};
-use crate::model::Model;
+use crate::interval::Interval;
+use crate::material::{Dielectric, DiffuseLight, Lambertian, Material, Metal};
+use crate::mesh::TriangleMesh;
+use crate::model::{Model, ModelOptions};
 use crate::quad::{Quad, Shape2D};
+use crate::ray::Ray;
 use crate::sphere::{Magnifier, Sphere};
 use crate::texture::{
     CheckerTexture, ImageTexture, NoiseTexture, SolidColor, StackedPaddedTexture,
 };
+use crate::transform::Transform;
+use crate::triangle::Triangle;
 use crate::vec3::{Point, Vec3f64};
 use rand::random_range;
+use std::f64::consts::PI;
 use std::sync::Arc;
+use std::time::Instant;
 
-fn bouncing_spheres() {
+fn bouncing_spheres(opts: &Options) -> Result<(), AssetError> {
     // World
 
     let mut world = HittableList::default();
@@ -129,15 +152,19 @@ fn bouncing_spheres() {
         c.defocus_angle = 0.6;
         c.focus_dist = 10.0;
 
+        opts.configure(&mut c);
+
         c.with_initialized()
     };
 
     if let Err(e) = camera.render(&world, "image.png") {
         eprintln!("Error: {e}");
     }
+
+    Ok(())
 }
 
-fn checkered_spheres() {
+fn checkered_spheres(opts: &Options) -> Result<(), AssetError> {
     let mut world = HittableList::default();
 
     let checker = Arc::new(CheckerTexture::from(
@@ -175,16 +202,20 @@ fn checkered_spheres() {
 
         c.defocus_angle = 0.0;
 
+        opts.configure(&mut c);
+
         c.with_initialized()
     };
 
     if let Err(e) = camera.render(&world, "image.png") {
         eprintln!("Error: {e}");
     }
+
+    Ok(())
 }
 
-fn earth() {
-    let earth_texture = Arc::new(ImageTexture::new("earthmap.jpg"));
+fn earth(opts: &Options) -> Result<(), AssetError> {
+    let earth_texture = Arc::new(ImageTexture::new("earthmap.jpg", &opts.assets)?);
     let earth_surface = Arc::new(Lambertian::new(earth_texture));
     let globe = Sphere::new(Point::zero(), 2.0, earth_surface);
 
@@ -204,15 +235,19 @@ fn earth() {
 
         c.defocus_angle = 0.0;
 
+        opts.configure(&mut c);
+
         c.with_initialized()
     };
 
     if let Err(e) = camera.render(&globe, "globe.png") {
         eprintln!("Error: {e}");
     }
+
+    Ok(())
 }
 
-fn perlin_spheres() {
+fn perlin_spheres(opts: &Options) -> Result<(), AssetError> {
     let mut world = HittableList::default();
 
     let pertext = Arc::new(NoiseTexture::new(4.0));
@@ -245,15 +280,19 @@ fn perlin_spheres() {
 
         c.defocus_angle = 0.0;
 
+        opts.configure(&mut c);
+
         c.with_initialized()
     };
 
     if let Err(e) = camera.render(&world, "perlin.png") {
         eprintln!("Error: {e}");
     }
+
+    Ok(())
 }
 
-fn quads() {
+fn quads(opts: &Options) -> Result<(), AssetError> {
     let mut world = HittableList::default();
 
     // Materials
@@ -264,7 +303,10 @@ fn quads() {
     ))));
     let back_green = Arc::new(Metal::new(Color::new(0.7, 1.0, 0.7), 0.005));
     let right_blue = Arc::new(Lambertian::new(Arc::new(NoiseTexture::new(0.5))));
-    let upper_orange = Arc::new(Lambertian::new(Arc::new(ImageTexture::new("earthmap.jpg"))));
+    let upper_orange = Arc::new(Lambertian::new(Arc::new(ImageTexture::new(
+        "earthmap.jpg",
+        &opts.assets,
+    )?)));
     let lower_teal = Arc::new(Lambertian::from(Color::new(0.2, 0.8, 0.8)));
 
     // Quads
@@ -326,15 +368,19 @@ fn quads() {
 
         c.defocus_angle = 0.0;
 
+        opts.configure(&mut c);
+
         c.with_initialized()
     };
 
     if let Err(e) = camera.render(&world, "quads.png") {
         eprintln!("Error: {e}");
     }
+
+    Ok(())
 }
 
-fn simple_light() {
+fn simple_light(opts: &Options) -> Result<(), AssetError> {
     let mut world = HittableList::default();
 
     let pertext = Arc::new(NoiseTexture::new(4.0));
@@ -381,15 +427,19 @@ fn simple_light() {
 
         c.defocus_angle = 0.0;
 
+        opts.configure(&mut c);
+
         c.with_initialized()
     };
 
     if let Err(e) = camera.render(&world, "light.png") {
         eprintln!("Error: {e}");
     }
+
+    Ok(())
 }
 
-fn cornell_box() {
+fn cornell_box(opts: &Options) -> Result<(), AssetError> {
     let mut world = HittableList::default();
 
     let white_texture = Arc::new(SolidColor::from(Color::new(0.73, 0.73, 0.73)));
@@ -402,7 +452,7 @@ fn cornell_box() {
     let mirror = Arc::new(Metal::new(Color::new(0.831, 0.686, 0.216), 0.01));
 
     let background1 = Arc::new(Lambertian::new(Arc::new(StackedPaddedTexture::new(
-        Arc::new(ImageTexture::new("pic1.jpg")),
+        Arc::new(ImageTexture::new("pic1.jpg", &opts.assets)?),
         white_texture.clone(),
         (0.00..1.00).into(),
         ((1.00 - 1.00 * 3712.0 / 5568.0)..1.00).into(),
@@ -488,15 +538,19 @@ fn cornell_box() {
 
         c.defocus_angle = 0.0;
 
+        opts.configure(&mut c);
+
         c.with_initialized()
     };
 
     if let Err(e) = camera.render(&world, "cornell_box.png") {
         eprintln!("Error: {e}");
     }
+
+    Ok(())
 }
 
-fn cornell_smoke() {
+fn cornell_smoke(opts: &Options) -> Result<(), AssetError> {
     let mut world = HittableList::default();
 
     let red = Arc::new(Lambertian::from(Color::new(0.65, 0.05, 0.05)));
@@ -582,15 +636,27 @@ fn cornell_smoke() {
 
         c.defocus_angle = 0.0;
 
+        opts.configure(&mut c);
+
         c.with_initialized()
     };
 
     if let Err(e) = camera.render(&world, "cornell_smoke.png") {
         eprintln!("Error: {e}");
     }
+
+    Ok(())
 }
 
-pub fn final_scene(image_width: i32, samples_per_pixel: i32, max_depth: i32) {
+// Objects of the final scene. The ground boxes and the cluster of spheres are put in BVHs of
+// their own, built along with the BVH of the whole scene.
+struct FinalSceneObjects {
+    objects: Vec<Arc<dyn Hittable>>,
+    ground_boxes: Vec<Arc<dyn Hittable>>,
+    sphere_cluster: Vec<Arc<dyn Hittable>>,
+}
+
+fn final_scene_objects(assets: &AssetResolver) -> Result<FinalSceneObjects, AssetError> {
     let mut world = HittableList::default();
 
     let ground = Arc::new(Lambertian::from(Color::new(0.48, 0.83, 0.53)));
@@ -614,9 +680,6 @@ pub fn final_scene(image_width: i32, samples_per_pixel: i32, max_depth: i32) {
             ))
         }
     }
-    let bvh1 = Arc::new(BVHNode::from(boxes1));
-    world.add(bvh1);
-
     let light = Arc::new(DiffuseLight::from(Color::all(7.0)));
     world.add(Arc::new(Quad::new(
         Point::new(123.0, 554.0, 147.0),
@@ -669,7 +732,10 @@ pub fn final_scene(image_width: i32, samples_per_pixel: i32, max_depth: i32) {
         Color::one(),
     )));
 
-    let emat = Arc::new(Lambertian::new(Arc::new(ImageTexture::new("earthmap.jpg"))));
+    let emat = Arc::new(Lambertian::new(Arc::new(ImageTexture::new(
+        "earthmap.jpg",
+        assets,
+    )?)));
     world.add(Arc::new(Sphere::new(
         Point::new(400.0, 200.0, 400.0),
         100.0,
@@ -691,34 +757,245 @@ pub fn final_scene(image_width: i32, samples_per_pixel: i32, max_depth: i32) {
         boxes2.push(Arc::new(Sphere::new(rand_point, 10.0, white.clone())));
     }
 
-    let bvh2 = Arc::new(BVHNode::from(boxes2));
+    Ok(FinalSceneObjects {
+        objects: world.objects,
+        ground_boxes: boxes1,
+        sphere_cluster: boxes2,
+    })
+}
+
+fn final_scene_world(scene: &FinalSceneObjects, options: &BVHOptions) -> BVHNode {
+    let mut world = scene.objects.clone();
+
+    let bvh1 = Arc::new(BVHNode::with_options(&scene.ground_boxes, options));
+    world.push(bvh1);
+
+    let bvh2 = Arc::new(BVHNode::with_options(&scene.sphere_cluster, options));
     let rotated = Arc::new(RotateY::new(bvh2, 15.0));
     let translated = Arc::new(Translate::new(rotated, Vec3f64::new(-100.0, 270.0, 395.0)));
-    world.add(translated);
+    world.push(translated);
 
-    let world = BVHNode::from(world);
+    BVHNode::with_options(&world, options)
+}
 
-    let camera = {
-        let mut c = Camera::default();
-        c.aspect_ratio = 1.0;
-        c.image_width = image_width;
-        c.samples_per_pixel = samples_per_pixel;
-        c.max_depth = max_depth;
-        c.background = Color::zero();
-        c.vfov = 40.0;
-        c.lookfrom = Point::new(478.0, 278.0, -600.0);
-        c.lookat = Point::new(278.0, 278.0, 0.0);
-        c.vup = Vec3f64::new(0.0, 1.0, 0.0);
-        c.defocus_angle = 0.0;
-        c.with_initialized()
-    };
+fn final_scene_camera(
+    opts: &Options,
+    image_width: i32,
+    samples_per_pixel: i32,
+    max_depth: i32,
+) -> Camera {
+    let mut c = Camera::default();
+    c.aspect_ratio = 1.0;
+    c.image_width = image_width;
+    c.samples_per_pixel = samples_per_pixel;
+    c.max_depth = max_depth;
+    c.background = Color::zero();
+    c.vfov = 40.0;
+    c.lookfrom = Point::new(478.0, 278.0, -600.0);
+    c.lookat = Point::new(278.0, 278.0, 0.0);
+    c.vup = Vec3f64::new(0.0, 1.0, 0.0);
+    c.defocus_angle = 0.0;
+    opts.configure(&mut c);
+    c.with_initialized()
+}
 
-    if let Err(e) = camera.render(&BVHNode::from(world), "final_scene.png") {
+pub fn final_scene(
+    opts: &Options,
+    image_width: i32,
+    samples_per_pixel: i32,
+    max_depth: i32,
+) -> Result<(), AssetError> {
+    let objects = final_scene_objects(&opts.assets)?;
+    let world = final_scene_world(&objects, &BVHOptions::default());
+    let camera = final_scene_camera(opts, image_width, samples_per_pixel, max_depth);
+
+    if let Err(e) = camera.render(&world, "final_scene.png") {
         eprintln!("Error: {e}");
     }
+
+    Ok(())
+}
+
+fn final_scene_benchmark(opts: &Options) -> Result<(), AssetError> {
+    // Measures the BVH build time and the path tracing throughput of the final scene, rendering
+    // into memory only so that image encoding doesn't skew the numbers. The median split
+    // builder, down to single objects, is compared with the SAH builder using the settings
+    // given on the command line, traversed as a binary tree and as a four-wide one. All of
+    // them get the same objects, and the same shadow rays between random points of the scene.
+    const RUNS: usize = 3;
+    const SHADOW_RAYS: usize = 200_000;
+
+    let objects = final_scene_objects(&opts.assets)?;
+    let camera = final_scene_camera(opts, 300, 16, 80);
+    let shadow_rays: Vec<Ray> = (0..SHADOW_RAYS)
+        .map(|_| {
+            let from = Point::random_range(0.0..555.0);
+            let to = Point::random_range(0.0..555.0);
+            Ray::new(from.clone(), to - from)
+        })
+        .collect();
+    let segment = Interval::from(0.0, 1.0);
+
+    let median = BVHOptions {
+        split: SplitMethod::Median,
+        max_leaf_size: 1,
+        wide: false,
+        ..Default::default()
+    };
+    let binary = BVHOptions {
+        wide: false,
+        ..opts.bvh.clone()
+    };
+    let wide = BVHOptions {
+        wide: true,
+        ..opts.bvh.clone()
+    };
+    for options in [median, binary, wide] {
+        let width = if options.wide { "four-wide" } else { "binary" };
+        match options.split {
+            SplitMethod::Median => println!(
+                "Median split, leaves of up to {} objects, {width}",
+                options.max_leaf_size
+            ),
+            SplitMethod::Sah => println!(
+                "SAH with {} bins, leaves of up to {} objects, {width}",
+                options.bins, options.max_leaf_size
+            ),
+        }
+
+        let start = Instant::now();
+        let world = final_scene_world(&objects, &options);
+        println!("BVH build: {:.3} ms", start.elapsed().as_secs_f64() * 1e3);
+
+        for run in 1..=RUNS {
+            let rays_before = camera.rays_traced();
+            let start = Instant::now();
+            camera.render_buffer(&world);
+            let seconds = start.elapsed().as_secs_f64();
+            let rays = camera.rays_traced() - rays_before;
+            println!(
+                "Render {run}/{RUNS}: {rays} rays in {seconds:.3} s, {:.3} Mrays/s",
+                rays as f64 / seconds / 1e6
+            );
+        }
+
+        // The media of the scene are hit at random, so the two counts can differ slightly.
+        let start = Instant::now();
+        let hit = shadow_rays
+            .iter()
+            .filter(|r| world.hit(r, segment).is_some())
+            .count();
+        let hit_ms = start.elapsed().as_secs_f64() * 1e3;
+        let start = Instant::now();
+        let occluded = shadow_rays
+            .iter()
+            .filter(|r| world.occluded(r, segment))
+            .count();
+        let occluded_ms = start.elapsed().as_secs_f64() * 1e3;
+        println!(
+            "{SHADOW_RAYS} shadow rays: closest hit in {hit_ms:.3} ms ({hit} blocked), \
+             any hit in {occluded_ms:.3} ms ({occluded} blocked)"
+        );
+    }
+
+    // Animates the sphere cluster, each sphere drifting at its own velocity, and compares
+    // updating its BVH by refitting with rebuilding it at every frame.
+    const FRAMES: usize = 8;
+    let velocities: Vec<Vec3f64> = (0..objects.sphere_cluster.len())
+        .map(|_| Vec3f64::random_range(-15.0..15.0))
+        .collect();
+    let mut animated = BVHNode::with_options(&objects.sphere_cluster, &opts.bvh);
+    println!("Animated cluster of {} spheres", velocities.len());
+    for frame in 1..=FRAMES {
+        let moved: Vec<Arc<dyn Hittable>> = objects
+            .sphere_cluster
+            .iter()
+            .zip(&velocities)
+            .map(|(sphere, v)| {
+                Arc::new(Translate::new(sphere.clone(), v * frame as f64)) as Arc<dyn Hittable>
+            })
+            .collect();
+
+        let start = Instant::now();
+        let rebuilt = animated.update(&moved);
+        let update_ms = start.elapsed().as_secs_f64() * 1e3;
+        let start = Instant::now();
+        let fresh = BVHNode::with_options(&moved, &opts.bvh);
+        let build_ms = start.elapsed().as_secs_f64() * 1e3;
+        let update = if rebuilt {
+            "refit and rebuild"
+        } else {
+            "refit"
+        };
+        println!(
+            "Frame {frame}/{FRAMES}: {update} in {update_ms:.3} ms, cost {:.2}; \
+             rebuild in {build_ms:.3} ms, cost {:.2}",
+            animated.cost(),
+            fresh.cost()
+        );
+    }
+
+    // BVH build times of a large mesh, on one thread and on all of them.
+    let (positions, triangles) = sphere_mesh(1000, 1000);
+    let material: Arc<dyn Material> = Arc::new(Lambertian::from(Color::new(0.73, 0.73, 0.73)));
+    let single = rayon::ThreadPoolBuilder::new()
+        .num_threads(1)
+        .build()
+        .unwrap();
+    let all = rayon::ThreadPoolBuilder::new().build().unwrap();
+    println!("Mesh of {} triangles", triangles.len());
+    for pool in [single, all] {
+        let start = Instant::now();
+        pool.install(|| {
+            TriangleMesh::new(
+                positions.clone(),
+                Vec::new(),
+                Vec::new(),
+                triangles.clone(),
+                vec![material.clone()],
+                Vec::new(),
+            )
+        });
+        println!(
+            "BVH build on {} thread(s): {:.3} ms",
+            pool.current_num_threads(),
+            start.elapsed().as_secs_f64() * 1e3
+        );
+    }
+
+    Ok(())
+}
+
+fn sphere_mesh(rings: u32, segments: u32) -> (Vec<Point>, Vec<[u32; 3]>) {
+    // Unit UV sphere with 2 * rings * segments triangles, including degenerate ones at the
+    // poles.
+    let mut positions = Vec::new();
+    for ring in 0..=rings {
+        let theta = PI * ring as f64 / rings as f64;
+        for segment in 0..=segments {
+            let phi = 2.0 * PI * segment as f64 / segments as f64;
+            positions.push(Point::new(
+                theta.sin() * phi.cos(),
+                theta.cos(),
+                theta.sin() * phi.sin(),
+            ));
+        }
+    }
+
+    let mut triangles = Vec::new();
+    let row = segments + 1;
+    for ring in 0..rings {
+        for segment in 0..segments {
+            let i = ring * row + segment;
+            triangles.push([i, i + row, i + 1]);
+            triangles.push([i + 1, i + row, i + row + 1]);
+        }
+    }
+
+    (positions, triangles)
 }
 
-fn model_load() {
+fn model_load(opts: &Options) -> Result<(), AssetError> {
     let mut world = HittableList::default();
 
     let white_texture = Arc::new(SolidColor::from(Color::new(0.73, 0.73, 0.73)));
@@ -767,21 +1044,60 @@ fn model_load() {
         white.clone(),
     ))); // back
 
-    let box1 = {
-        let mut b: Arc<dyn Hittable> = Arc::new(Model::with_mat("bunny.obj", gold, 1400.0));
-        b = Arc::new(RotateY::new(b, 165.0));
-        let y_move = -*&b.bounding_box()[1].min;
-        Arc::new(Translate::new(b, Vec3f64::new(130.0, y_move, 200.0)))
-    };
-    world.add(box1);
+    // A single triangle leaning in the back corner. Its vertex normals spread outwards, so it
+    // shades like a curved patch although it is flat.
+    let corner = [
+        Point::new(555.0, 0.0, 555.0),
+        Point::new(400.0, 0.0, 555.0),
+        Point::new(555.0, 200.0, 480.0),
+    ];
+    let outwards = [
+        Vec3f64::new(-1.0, 0.3, -1.0).into_unit_vector(),
+        Vec3f64::new(-0.3, 0.3, -1.0).into_unit_vector(),
+        Vec3f64::new(-1.0, 1.0, -0.3).into_unit_vector(),
+    ];
+    world.add(Arc::new(Triangle::with_vertex_data(
+        corner,
+        Some(outwards),
+        None,
+        Arc::new(Lambertian::from(Color::new(0.8, 0.6, 0.3))),
+    )));
 
-    let box2 = {
-        let mut b: Arc<dyn Hittable> = Arc::new(Model::new("usagi-chiikawa.obj", 230.0));
-        b = Arc::new(RotateY::new(b, 190.0));
-        let y_move = -*&b.bounding_box()[1].min;
-        Arc::new(Translate::new(b, Vec3f64::new(400.0, y_move, 350.0)))
+    // Both models stand on the floor, centered on their placement before being turned.
+    let placement = ModelOptions {
+        center: true,
+        ground: Some(0.0),
+        ..Default::default()
     };
-    world.add(box2);
+
+    let bunny = Model::with_mat(
+        "bunny.obj",
+        gold,
+        &ModelOptions {
+            height: Some(210.0),
+            ..placement.clone()
+        },
+        &opts.assets,
+    )?;
+    let bunny = Arc::new(RotateY::new(Arc::new(bunny), 165.0));
+    world.add(Arc::new(Translate::new(
+        bunny,
+        Vec3f64::new(190.0, 0.0, 200.0),
+    )));
+
+    let usagi = Model::new(
+        "usagi-chiikawa.obj",
+        &ModelOptions {
+            fit: Some(Vec3f64::new(200.0, 260.0, 200.0)),
+            ..placement
+        },
+        &opts.assets,
+    )?;
+    let usagi = Arc::new(RotateY::new(Arc::new(usagi), 190.0));
+    world.add(Arc::new(Translate::new(
+        usagi,
+        Vec3f64::new(380.0, 0.0, 350.0),
+    )));
 
     let world = BVHNode::from(world);
 
@@ -801,15 +1117,19 @@ fn model_load() {
 
         c.defocus_angle = 0.0;
 
+        opts.configure(&mut c);
+
         c.with_initialized()
     };
 
     if let Err(e) = camera.render(&world, "model_load.png") {
         eprintln!("Error: {e}");
     }
+
+    Ok(())
 }
 
-fn magnifier_simulation() {
+fn magnifier_simulation(opts: &Options) -> Result<(), AssetError> {
     let mut world = HittableList::default();
 
     let white = Arc::new(Lambertian::from(Color::all(0.7)));
@@ -852,27 +1172,172 @@ fn magnifier_simulation() {
 
         c.defocus_angle = 0.0;
 
+        opts.configure(&mut c);
+
         c.with_initialized()
     };
 
     if let Err(e) = camera.render(&world, "magnifier_simulation.png") {
         eprintln!("Error: {e}");
     }
+
+    Ok(())
+}
+
+fn instances(opts: &Options) -> Result<(), AssetError> {
+    // 500 copies of one model share a single mesh and its BVH, each with its own transform and
+    // material. The scene BVH is built over the instances only.
+    let mut world = HittableList::default();
+
+    let ground = Arc::new(Lambertian::from(Color::new(0.48, 0.83, 0.53)));
+    world.add(Arc::new(Quad::new(
+        Point::new(-100.0, 0.0, -100.0),
+        Vec3f64::new(200.0, 0.0, 0.0),
+        Vec3f64::new(0.0, 0.0, 200.0),
+        ground,
+    )));
+
+    let bunny: Arc<dyn Hittable> = Arc::new(Model::new(
+        "bunny.obj",
+        &ModelOptions {
+            height: Some(1.5),
+            center: true,
+            ground: Some(0.0),
+            ..Default::default()
+        },
+        &opts.assets,
+    )?);
+
+    let mut bunnies = HittableList::default();
+    for i in 0..20 {
+        for j in 0..25 {
+            let scale = random_range(0.6..1.2);
+            let transform = Transform::scale(&Vec3f64::all(scale))
+                .then(&Transform::rotate::<1>(random_range(0.0..360.0)))
+                .then(&Transform::translate(&Point::new(
+                    -19.0 + 2.0 * i as f64 + random_range(-0.3..0.3),
+                    0.0,
+                    -24.0 + 2.0 * j as f64 + random_range(-0.3..0.3),
+                )));
+
+            let mat: Arc<dyn Material> = if random_range(0.0..1.0) < 0.8 {
+                Arc::new(Lambertian::from(Color::random() * Color::random()))
+            } else {
+                Arc::new(Metal::new(
+                    Color::random_range(0.5..1.0),
+                    random_range(0.0..0.3),
+                ))
+            };
+
+            bunnies.add(Arc::new(Instance::with_mat(bunny.clone(), transform, mat)));
+        }
+    }
+    world.add(Arc::new(BVHNode::from(bunnies)));
+
+    let world = BVHNode::from(world);
+
+    let camera = {
+        let mut c = Camera::default();
+
+        c.aspect_ratio = 16.0 / 9.0;
+        c.image_width = 800;
+        c.samples_per_pixel = 100;
+        c.max_depth = 50;
+        c.background = Color::new(0.70, 0.80, 1.00);
+
+        c.vfov = 40.0;
+        c.lookfrom = Point::new(0.0, 14.0, 34.0);
+        c.lookat = Point::new(0.0, 0.0, 0.0);
+        c.vup = Vec3f64::new(0.0, 1.0, 0.0);
+
+        c.defocus_angle = 0.0;
+
+        opts.configure(&mut c);
+
+        c.with_initialized()
+    };
+
+    if let Err(e) = camera.render(&world, "instances.png") {
+        eprintln!("Error: {e}");
+    }
+
+    Ok(())
+}
+
+fn gltf_scene(opts: &Options) -> Result<(), AssetError> {
+    let filename = opts.gltf.as_deref().unwrap_or("scene.gltf");
+    let scene = GltfScene::load(filename, &opts.assets)?;
+    let has_lights = scene.has_lights;
+    let world = BVHNode::from(scene.world);
+
+    // Use the first camera of the file, or look at the whole scene from the front.
+    let mut c = scene.cameras.into_iter().next().unwrap_or_else(|| {
+        let bbox = world.bounding_box();
+        let center = Point::new(
+            (bbox[0].min + bbox[0].max) / 2.0,
+            (bbox[1].min + bbox[1].max) / 2.0,
+            (bbox[2].min + bbox[2].max) / 2.0,
+        );
+        let extent = bbox[0].size().max(bbox[1].size()).max(bbox[2].size());
+
+        let mut c = Camera::default();
+        c.aspect_ratio = 16.0 / 9.0;
+        c.vfov = 40.0;
+        c.lookfrom = &center + Vec3f64::new(0.0, 0.0, 2.0 * extent);
+        c.lookat = center;
+        c.vup = Vec3f64::new(0.0, 1.0, 0.0);
+        c.defocus_angle = 0.0;
+        c
+    });
+
+    c.sunlight_dir = scene.sunlight_dir;
+    c.image_width = 600;
+    c.samples_per_pixel = 100;
+    c.max_depth = 50;
+    c.background = if has_lights {
+        Color::zero()
+    } else {
+        Color::new(0.70, 0.80, 1.00)
+    };
+
+    opts.configure(&mut c);
+
+    let camera = c.with_initialized();
+
+    if let Err(e) = camera.render(&world, "gltf_scene.png") {
+        eprintln!("Error: {e}");
+    }
+
+    Ok(())
 }
 
 fn main() {
-    match 11 {
-        1 => bouncing_spheres(),
-        2 => checkered_spheres(),
-        3 => earth(),
-        4 => perlin_spheres(),
-        5 => quads(),
-        6 => simple_light(),
-        7 => cornell_box(),
-        8 => cornell_smoke(),
-        9 => final_scene(1600, 10000, 80),
-        10 => model_load(),
-        11 => magnifier_simulation(),
-        _ => final_scene(400, 250, 4),
+    let opts = Options::from_args();
+
+    let result = if opts.bench {
+        final_scene_benchmark(&opts)
+    } else {
+        match opts.scene.unwrap_or(11) {
+            1 => bouncing_spheres(&opts),
+            2 => checkered_spheres(&opts),
+            3 => earth(&opts),
+            4 => perlin_spheres(&opts),
+            5 => quads(&opts),
+            6 => simple_light(&opts),
+            7 => cornell_box(&opts),
+            8 => cornell_smoke(&opts),
+            9 => final_scene(&opts, 1600, 10000, 80),
+            10 => model_load(&opts),
+            11 => magnifier_simulation(&opts),
+            12 => instances(&opts),
+            13 => gltf_scene(&opts),
+            _ => final_scene(&opts, 400, 250, 4),
+        }
     };
+
+    // Asset loading errors abort the scene, report them instead of panicking.
+    if let Err(e) = result {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
 }
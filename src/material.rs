@@ -1,18 +1,29 @@
 use crate::color::Color;
 use crate::hittable::HitRecord;
 use crate::ray::Ray;
+use crate::sampler::Sampler;
 use crate::texture::{SolidColor, Texture};
-use crate::vec3::{Point, Vec3f64};
+use crate::vec3::Vec3f64;
 use std::sync::Arc;
 
 pub trait Material: Send + Sync {
-    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord) -> Option<(Ray, Color)> {
+    fn scatter(
+        &self,
+        _r_in: &Ray,
+        _rec: &HitRecord,
+        _sampler: &mut dyn Sampler,
+    ) -> Option<(Ray, Color)> {
         None
     }
 
-    fn emitted(&self, _u: f64, _v: f64, _p: &Point) -> Color {
+    fn emitted(&self, _rec: &HitRecord) -> Color {
         Color::zero()
     }
+
+    // Surface color used as a guide by the denoiser.
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        Color::one()
+    }
 }
 
 pub struct Lambertian {
@@ -30,18 +41,28 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
-        let mut scatter_direction = &rec.normal + Vec3f64::random_unit_vector();
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Ray, Color)> {
+        let mut scatter_direction =
+            &rec.normal + Vec3f64::unit_vector_from_sample(sampler.get_2d());
 
         // Catch degenerate scatter direction
         if scatter_direction.near_zero() {
             scatter_direction = rec.normal.clone();
         }
 
-        let scattered = Ray::with_time(rec.p.clone(), scatter_direction, r_in.time());
-        let attenuation = self.tex.value(rec.u, rec.v, &rec.p);
+        let scattered = rec.spawn_ray(scatter_direction, r_in.time());
+        let attenuation = self.tex.filtered(rec.u, rec.v, &rec.p, &rec.uv_derivatives) * &rec.color;
         Some((scattered, attenuation))
     }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.tex.filtered(rec.u, rec.v, &rec.p, &rec.uv_derivatives) * &rec.color
+    }
 }
 
 pub struct Metal {
@@ -56,28 +77,51 @@ impl Metal {
 }
 
 impl Material for Metal {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Ray, Color)> {
         let mut reflected = r_in.direction().reflect(&rec.normal);
-        reflected = reflected.into_unit_vector() + (Vec3f64::random_unit_vector() * self.fuzz);
+        reflected = reflected.into_unit_vector()
+            + (Vec3f64::unit_vector_from_sample(sampler.get_2d()) * self.fuzz);
         if reflected.dot(&rec.normal) > 0.0 {
-            let scattered = Ray::with_time(rec.p.clone(), reflected, r_in.time());
-            let attenuation = self.albedo.clone();
+            let mut scattered = rec.spawn_ray(reflected, r_in.time());
+            if self.fuzz == 0.0 {
+                // Only a mirror keeps the pixel footprint coherent.
+                let differential = rec.spawn_differential(r_in, |d| Some(d.reflect(&rec.normal)));
+                scattered = scattered.with_differential(differential);
+            }
+            let attenuation = &self.albedo * &rec.color;
             Some((scattered, attenuation))
         } else {
             None
         }
     }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        &self.albedo * &rec.color
+    }
 }
 
 pub struct Dielectric {
     // Refractive index in vacuum or air, or the ratio of the material's refractive index over
     // the refractive index of the enclosing media
     refraction_index: f64,
+    tint: Color, // Attenuation of every reflected or transmitted ray
 }
 
 impl Dielectric {
     pub fn new(refraction_index: f64) -> Self {
-        Self { refraction_index }
+        Self::with_tint(refraction_index, Color::one())
+    }
+
+    pub fn with_tint(refraction_index: f64, tint: Color) -> Self {
+        Self {
+            refraction_index,
+            tint,
+        }
     }
 
     fn reflectance(cosine: f64, refraction_index: f64) -> f64 {
@@ -89,7 +133,12 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Ray, Color)> {
         let ri = if rec.front_face {
             1.0 / self.refraction_index
         } else {
@@ -101,17 +150,27 @@ impl Material for Dielectric {
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
 
         let cannot_refract = ri * sin_theta > 1.0;
-        let direction =
-            if cannot_refract || Self::reflectance(cos_theta, ri) > rand::random_range(0.0..1.0) {
-                unit_direction.reflect(&rec.normal)
-            } else {
-                unit_direction.refract(&rec.normal, ri)
-            };
+        let scattered = if cannot_refract || Self::reflectance(cos_theta, ri) > sampler.get_1d() {
+            let differential = rec.spawn_differential(r_in, |d| Some(d.reflect(&rec.normal)));
+            rec.spawn_ray(unit_direction.reflect(&rec.normal), r_in.time())
+                .with_differential(differential)
+        } else {
+            // The offset rays refract on their own, unless they are past the critical angle.
+            let differential = rec.spawn_differential(r_in, |d| {
+                let d = d.unit_vector();
+                let cos_theta = (-d.dot(&rec.normal)).min(1.0);
+                (ri * (1.0 - cos_theta * cos_theta).sqrt() <= 1.0)
+                    .then(|| d.refract(&rec.normal, ri))
+            });
+            rec.spawn_ray(unit_direction.refract(&rec.normal, ri), r_in.time())
+                .with_differential(differential)
+        };
 
-        Some((
-            Ray::with_time(rec.p.clone(), direction, r_in.time()),
-            Color::one(),
-        ))
+        Some((scattered, self.tint.clone()))
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.tint.clone()
     }
 }
 
@@ -130,8 +189,12 @@ impl DiffuseLight {
 }
 
 impl Material for DiffuseLight {
-    fn emitted(&self, u: f64, v: f64, p: &Point) -> Color {
-        self.tex.value(u, v, p)
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        self.tex.filtered(rec.u, rec.v, &rec.p, &rec.uv_derivatives)
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.tex.filtered(rec.u, rec.v, &rec.p, &rec.uv_derivatives)
     }
 }
 
@@ -150,9 +213,94 @@ impl Isotropic {
 }
 
 impl Material for Isotropic {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
-        let scattered = Ray::with_time(rec.p.clone(), Vec3f64::random_unit_vector(), r_in.time());
-        let attenuation = self.tex.value(rec.u, rec.v, &rec.p);
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Ray, Color)> {
+        let direction = Vec3f64::unit_vector_from_sample(sampler.get_2d());
+        let scattered = rec.spawn_ray(direction, r_in.time());
+        let attenuation = self.tex.filtered(rec.u, rec.v, &rec.p, &rec.uv_derivatives);
         Some((scattered, attenuation))
     }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.tex.filtered(rec.u, rec.v, &rec.p, &rec.uv_derivatives)
+    }
+}
+
+/// Metallic-roughness material, as used by glTF.
+///
+/// Each scattering event picks the metallic lobe, a fuzzy reflection tinted by the base color,
+/// with a probability equal to the metalness, and the diffuse lobe otherwise. The specular layer
+/// of non-metals is not modeled.
+pub struct MetallicRoughness {
+    base_color: Arc<dyn Texture>,
+    metallic_roughness: Arc<dyn Texture>, // Roughness in the green channel, metalness in blue
+    emission: Arc<dyn Texture>,
+}
+
+impl MetallicRoughness {
+    pub fn new(
+        base_color: Arc<dyn Texture>,
+        metallic_roughness: Arc<dyn Texture>,
+        emission: Arc<dyn Texture>,
+    ) -> Self {
+        Self {
+            base_color,
+            metallic_roughness,
+            emission,
+        }
+    }
+}
+
+impl Material for MetallicRoughness {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Ray, Color)> {
+        let attenuation = self
+            .base_color
+            .filtered(rec.u, rec.v, &rec.p, &rec.uv_derivatives)
+            * &rec.color;
+        let metallic_roughness =
+            self.metallic_roughness
+                .filtered(rec.u, rec.v, &rec.p, &rec.uv_derivatives);
+        let (roughness, metallic) = (metallic_roughness[1], metallic_roughness[2]);
+
+        let lobe = sampler.get_1d();
+        let direction = Vec3f64::unit_vector_from_sample(sampler.get_2d());
+        let direction = if lobe < metallic {
+            let reflected = r_in.direction().reflect(&rec.normal).into_unit_vector();
+            let reflected = reflected + direction * roughness;
+            if reflected.dot(&rec.normal) <= 0.0 {
+                return None;
+            }
+            reflected
+        } else {
+            let scatter_direction = &rec.normal + direction;
+            if scatter_direction.near_zero() {
+                rec.normal.clone()
+            } else {
+                scatter_direction
+            }
+        };
+
+        let scattered = rec.spawn_ray(direction, r_in.time());
+        Some((scattered, attenuation))
+    }
+
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        self.emission
+            .filtered(rec.u, rec.v, &rec.p, &rec.uv_derivatives)
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.base_color
+            .filtered(rec.u, rec.v, &rec.p, &rec.uv_derivatives)
+            * &rec.color
+    }
 }
@@ -0,0 +1,339 @@
+use crate::aabb::AABB;
+use crate::bvh::{BVHOptions, LinearBVH};
+use crate::cache::{CacheReader, CacheWriter};
+use crate::color::Color;
+use crate::hittable::{Hit, HitRecord, Hittable, SurfacePoint};
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::texture::NormalMap;
+use crate::transform::Transform;
+use crate::triangle::{self, UV};
+use crate::vec3::{Point, Real, Vec3, Vec3f64, widen};
+use std::io;
+use std::sync::Arc;
+
+/// Indexed triangle mesh.
+///
+/// Vertex attributes are stored once and shared by all the triangles using them, each triangle
+/// is three vertex indices and a material index, and a BVH over the triangle indices is built
+/// when the mesh is created. Positions, normals and texture coordinates are stored with the
+/// precision of `Real`, the triangles are intersected in f64.
+pub struct TriangleMesh {
+    positions: Vec<Vec3<Real>>,
+    normals: Vec<Vec3<Real>>, // Per-vertex shading normals, empty if the mesh has none
+    uvs: Vec<[Real; 2]>,      // Per-vertex texture coordinates, empty if the mesh has none
+    triangles: Vec<[u32; 3]>,
+    materials: Vec<Arc<dyn Material>>,
+    material_ids: Vec<u32>, // Index into materials of each triangle, empty if all use the first
+    normal_maps: Vec<Option<Arc<NormalMap>>>, // Normal map of each material, may be empty
+    colors: Vec<Color>,     // Per-vertex colors, empty if the mesh has none
+
+    bvh: LinearBVH,
+}
+
+impl TriangleMesh {
+    pub fn new(
+        positions: Vec<Point>,
+        normals: Vec<Vec3f64>,
+        uvs: Vec<UV>,
+        triangles: Vec<[u32; 3]>,
+        materials: Vec<Arc<dyn Material>>,
+        material_ids: Vec<u32>,
+    ) -> Self {
+        assert!(!materials.is_empty(), "a mesh needs at least one material");
+
+        // Triangles are sorted into the leaves of the BVH, then the triangle and material
+        // arrays are reordered to match so that leaves only need a range. The boxes are those
+        // of the stored vertices, which may have been rounded.
+        let positions: Vec<Vec3<Real>> = positions.iter().map(Vec3::to_real).collect();
+        let normals = normals.iter().map(Vec3::to_real).collect();
+        let uvs = uvs.iter().map(|&(u, v)| [u as Real, v as Real]).collect();
+        let bboxes: Vec<AABB> = triangles
+            .iter()
+            .map(|tri| {
+                let [p0, p1, p2] = tri.map(|i| positions[i as usize].to_f64());
+                AABB::from_aabbs(&AABB::from_points(&p0, &p1), &AABB::from_points(&p1, &p2))
+            })
+            .collect();
+        let (bvh, order) = LinearBVH::new(&bboxes, &BVHOptions::default());
+
+        let triangles = order.iter().map(|&i| triangles[i as usize]).collect();
+        let material_ids = if material_ids.is_empty() {
+            material_ids
+        } else {
+            order.iter().map(|&i| material_ids[i as usize]).collect()
+        };
+
+        Self {
+            positions,
+            normals,
+            uvs,
+            triangles,
+            materials,
+            material_ids,
+            normal_maps: Vec::new(),
+            colors: Vec::new(),
+            bvh,
+        }
+    }
+
+    pub fn with_normal_maps(mut self, normal_maps: Vec<Option<Arc<NormalMap>>>) -> Self {
+        // Normal maps perturb the shading normal in the tangent frame given by the texture
+        // coordinates, so they only apply to meshes with texture coordinates.
+        self.normal_maps = normal_maps;
+        self
+    }
+
+    pub fn with_vertex_colors(mut self, colors: Vec<Color>) -> Self {
+        self.colors = colors;
+        self
+    }
+
+    pub fn transformed(mut self, transform: &Transform) -> Self {
+        // Moves the vertices in place. The BVH keeps its structure, only the node boxes are
+        // replaced by the bounds of their transformed corners, which stay tight for the scales,
+        // translations and quarter turns used to place models.
+        for p in &mut self.positions {
+            *p = transform.point(&p.to_f64()).to_real();
+        }
+        for n in &mut self.normals {
+            *n = transform.normal(&n.to_f64()).to_real();
+        }
+        self.bvh.transform(transform);
+        self
+    }
+
+    pub fn has_vertex_colors(&self) -> bool {
+        !self.colors.is_empty()
+    }
+
+    pub fn write_cache(&self, out: &mut CacheWriter) {
+        // Stores the vertex attributes, triangles and BVH. Materials can't be stored, they
+        // are given again when reading.
+        for attribute in [&self.positions, &self.normals] {
+            out.len(attribute.len());
+            attribute.iter().for_each(|v| out.vec3(&v.to_f64()));
+        }
+        out.len(self.colors.len());
+        self.colors.iter().for_each(|c| out.vec3(c));
+        out.len(self.uvs.len());
+        for &[u, v] in &self.uvs {
+            out.f64(widen(u));
+            out.f64(widen(v));
+        }
+        out.len(self.triangles.len());
+        self.triangles.iter().flatten().for_each(|&i| out.u32(i));
+        out.len(self.material_ids.len());
+        self.material_ids.iter().for_each(|&id| out.u32(id));
+        self.bvh.write_cache(out);
+    }
+
+    pub fn read_cache(
+        input: &mut CacheReader,
+        materials: Vec<Arc<dyn Material>>,
+        normal_maps: Vec<Option<Arc<NormalMap>>>,
+    ) -> io::Result<Self> {
+        // Reads a mesh written by write_cache, checking that the indices it holds are in range.
+        // Material indices are checked against the materials given, which may have changed
+        // since the mesh was stored.
+        let vec3s = |input: &mut CacheReader| {
+            let len = input.len(3 * 8)?;
+            (0..len)
+                .map(|_| input.vec3())
+                .collect::<io::Result<Vec<_>>>()
+        };
+        let positions: Vec<_> = vec3s(input)?.iter().map(Vec3::to_real).collect();
+        let normals: Vec<_> = vec3s(input)?.iter().map(Vec3::to_real).collect();
+        let colors = vec3s(input)?;
+        let len = input.len(2 * 8)?;
+        let uvs = (0..len)
+            .map(|_| Ok([input.f64()? as Real, input.f64()? as Real]))
+            .collect::<io::Result<Vec<_>>>()?;
+        let len = input.len(3 * 4)?;
+        let triangles = (0..len)
+            .map(|_| Ok([input.u32()?, input.u32()?, input.u32()?]))
+            .collect::<io::Result<Vec<_>>>()?;
+        let len = input.len(4)?;
+        let material_ids = (0..len)
+            .map(|_| input.u32())
+            .collect::<io::Result<Vec<_>>>()?;
+        let bvh = LinearBVH::read_cache(input, triangles.len())?;
+
+        let vertex_count = positions.len();
+        let valid = [normals.len(), colors.len(), uvs.len()]
+            .iter()
+            .all(|&len| len == 0 || len == vertex_count)
+            && triangles
+                .iter()
+                .flatten()
+                .all(|&i| (i as usize) < vertex_count)
+            && (material_ids.is_empty() || material_ids.len() == triangles.len())
+            && material_ids
+                .iter()
+                .all(|&id| (id as usize) < materials.len());
+        if !valid || materials.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid mesh in cache file",
+            ));
+        }
+
+        Ok(Self {
+            positions,
+            normals,
+            uvs,
+            triangles,
+            materials,
+            material_ids,
+            normal_maps,
+            colors,
+            bvh,
+        })
+    }
+
+    fn vertices(&self, tri: usize) -> [Point; 3] {
+        self.triangles[tri].map(|i| self.positions[i as usize].to_f64())
+    }
+
+    fn uvs(&self, tri: usize) -> [UV; 3] {
+        self.triangles[tri].map(|i| {
+            let [u, v] = self.uvs[i as usize];
+            (widen(u), widen(v))
+        })
+    }
+
+    fn surface_interaction(&self, r: &Ray, tri: usize, t: f64, b1: f64, b2: f64) -> HitRecord {
+        let [i0, i1, i2] = self.triangles[tri].map(|i| i as usize);
+        let [p0, p1, p2] = self.vertices(tri);
+        let b0 = 1.0 - b1 - b2;
+
+        let shading_normal = if self.normals.is_empty() {
+            None
+        } else {
+            let n = [i0, i1, i2].map(|i| self.normals[i].to_f64());
+            let n = triangle::interpolate(&n, b1, b2);
+            (!n.near_zero()).then(|| n.into_unit_vector())
+        };
+
+        // Without vertex normals the winding order decides the outward side, otherwise the
+        // geometric normal is flipped to agree with the shading normal.
+        let mut normal = (&p1 - &p0).cross(&(&p2 - &p0)).into_unit_vector();
+        if let Some(n) = &shading_normal
+            && normal.dot(n) < 0.0
+        {
+            normal = -normal;
+        }
+
+        let uv = if self.uvs.is_empty() {
+            (b1, b2)
+        } else {
+            let [uv0, uv1, uv2] = self.uvs(tri);
+            (
+                b0 * uv0.0 + b1 * uv1.0 + b2 * uv2.0,
+                b0 * uv0.1 + b1 * uv1.1 + b2 * uv2.1,
+            )
+        };
+
+        let dpduv = self.dpduv(tri);
+        let mat_id = self.material_ids.get(tri).copied().unwrap_or(0) as usize;
+        let shading_normal = match (self.normal_maps.get(mat_id), &dpduv) {
+            (Some(Some(normal_map)), Some((dpdu, dpdv))) if !self.uvs.is_empty() => {
+                let n = shading_normal.clone().unwrap_or_else(|| normal.clone());
+                Self::tangent_frame(&n, dpdu, dpdv)
+                    .map(|(tangent, bitangent)| {
+                        let m = normal_map.normal(uv.0, uv.1);
+                        (tangent * m[0] + bitangent * m[1] + n * m[2]).into_unit_vector()
+                    })
+                    .or(shading_normal)
+            }
+            _ => shading_normal,
+        };
+
+        let (p, p_error) = triangle::hit_point([&p0, &p1, &p2], b1, b2);
+        let mut rec = HitRecord::new(r, t, p, normal, self.materials[mat_id].clone(), uv)
+            .with_p_error(p_error);
+        if let Some((dpdu, dpdv)) = dpduv {
+            rec = rec.with_dpduv(dpdu, dpdv);
+        }
+        if !self.colors.is_empty() {
+            let c = [i0, i1, i2].map(|i| self.colors[i].clone());
+            rec.color = triangle::interpolate(&c, b1, b2);
+        }
+        match shading_normal {
+            Some(n) => rec.with_shading_normal(n),
+            None => rec,
+        }
+    }
+
+    fn dpduv(&self, tri: usize) -> Option<(Vec3f64, Vec3f64)> {
+        // The barycentric coordinates b1, b2 stand in for missing texture coordinates.
+        let [p0, p1, p2] = self.vertices(tri);
+        if self.uvs.is_empty() {
+            return Some((&p1 - &p0, &p2 - &p0));
+        }
+        triangle::dpduv([&p0, &p1, &p2], self.uvs(tri))
+    }
+
+    fn tangent_frame(n: &Vec3f64, dpdu: &Vec3f64, dpdv: &Vec3f64) -> Option<(Vec3f64, Vec3f64)> {
+        // Returns the unit tangent and bitangent along the directions of increasing u and v, made
+        // orthogonal to the normal n. None if dpdu is along the normal.
+        let tangent = dpdu - n * n.dot(dpdu);
+        if tangent.near_zero() {
+            return None;
+        }
+        let tangent = tangent.into_unit_vector();
+
+        let mut bitangent = n.cross(&tangent);
+        if bitangent.dot(dpdv) < 0.0 {
+            bitangent = -bitangent;
+        }
+
+        Some((tangent, bitangent))
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn closest_hit(&self, r: &Ray, ray_t: Interval) -> Option<Hit> {
+        let mut closest = None;
+        self.bvh.hit(r, ray_t, |tri, interval| {
+            let (t, b1, b2) = triangle::intersect(self.vertices(tri).each_ref(), r, interval)?;
+            closest = Some((tri, t, b1, b2));
+            Some(t)
+        });
+
+        let (tri, t, b1, b2) = closest?;
+        let mut hit = Hit::with_coords(t, (b1, b2));
+        hit.push(tri);
+        Some(hit)
+    }
+
+    fn interaction(&self, r: &Ray, hit: &mut Hit) -> HitRecord {
+        let (b1, b2) = hit.coords;
+        self.surface_interaction(r, hit.pop(), hit.t, b1, b2)
+    }
+
+    fn surface_point(&self, _r: &Ray, hit: &mut Hit) -> SurfacePoint {
+        // Offsetting from the point works with the normal facing either side, so the winding
+        // normal is enough without looking at the vertex normals.
+        let (b1, b2) = hit.coords;
+        let [p0, p1, p2] = self.vertices(hit.pop());
+        let (p, p_error) = triangle::hit_point([&p0, &p1, &p2], b1, b2);
+        SurfacePoint {
+            t: hit.t,
+            p,
+            p_error,
+            normal: (&p1 - &p0).cross(&(&p2 - &p0)).into_unit_vector(),
+        }
+    }
+
+    fn occluded(&self, r: &Ray, ray_t: Interval) -> bool {
+        self.bvh.occluded(r, ray_t, |tri, interval| {
+            triangle::intersect(self.vertices(tri).each_ref(), r, interval).is_some()
+        })
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        self.bvh.bounding_box()
+    }
+}
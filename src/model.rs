@@ -1,186 +1,580 @@
 use crate::aabb::AABB;
-use crate::bvh::BVHNode;
+use crate::asset::{AssetError, AssetKind, AssetResolver};
+use crate::cache::{CacheReader, CacheWriter, Fnv1a};
 use crate::color::Color;
-use crate::hittable::{HitRecord, Hittable};
+use crate::hittable::{Hit, HitRecord, Hittable, SurfacePoint};
 use crate::interval::Interval;
-use crate::material::{Lambertian, Material};
-use crate::quad::{Quad, Shape2D};
+use crate::material::{Dielectric, DiffuseLight, Lambertian, Material, Metal};
+use crate::mesh::TriangleMesh;
+use crate::ply;
 use crate::ray::Ray;
-use crate::texture::{ImageTexture, SubTexture};
+use crate::texture::{ImageTexture, NormalMap};
+use crate::transform::Transform;
 use crate::vec3::{Point, Vec3f64};
-use std::env;
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-// Assume single object, single texture
+type MaterialEntry = (Arc<dyn Material>, Option<Arc<NormalMap>>); // Material and its normal map
+type MaterialTable = (Vec<Arc<dyn Material>>, Vec<Option<Arc<NormalMap>>>); // Both per material
+
+const OBJ_LOAD_OPTIONS: tobj::LoadOptions = tobj::LoadOptions {
+    triangulate: true,
+    single_index: true,
+    ignore_lines: true,
+    ignore_points: true,
+};
+
+/// Placement of a model in the scene, applied to its vertices when it is loaded.
+///
+/// The steps are applied in order: the up-axis conversion, the scale or the fit into a target
+/// size, then the centering and the drop onto the ground plane.
+#[derive(Clone)]
+pub struct ModelOptions {
+    pub z_up: bool,           // The model is Z-up, rotate it to Y-up
+    pub scale: f64,           // Uniform scale, unless the model is fit into a size or height
+    pub fit: Option<Vec3f64>, // Scale uniformly to fit into a box of this size
+    pub height: Option<f64>,  // Scale uniformly to this height
+    pub center: bool,         // Center the model on the origin
+    pub ground: Option<f64>,  // Move the model vertically to rest on this height
+}
+
+impl Default for ModelOptions {
+    fn default() -> Self {
+        Self {
+            z_up: false,
+            scale: 1.0,
+            fit: None,
+            height: None,
+            center: false,
+            ground: None,
+        }
+    }
+}
+
+impl ModelOptions {
+    fn transform(&self, bbox: &AABB) -> Transform {
+        // Returns the transform placing a model with the given bounds.
+        let mut transform = if self.z_up {
+            Transform::rotate::<0>(-90.0)
+        } else {
+            Transform::identity()
+        };
+        let bbox = transform.bbox(bbox);
+
+        // Fitting picks the largest scale keeping every side within its target size.
+        let mut targets = Vec::new();
+        if let Some(fit) = &self.fit {
+            targets.extend((0..3).map(|axis| fit[axis] / bbox[axis].size()));
+        }
+        if let Some(height) = self.height {
+            targets.push(height / bbox[1].size());
+        }
+        let scale = targets.into_iter().reduce(f64::min).unwrap_or(self.scale);
+        transform = transform.then(&Transform::scale(&Vec3f64::all(scale)));
+
+        let mut offset = Vec3f64::zero();
+        if self.center {
+            for axis in 0..3 {
+                offset[axis] = -scale * (bbox[axis].min + bbox[axis].max) / 2.0;
+            }
+        }
+        if let Some(ground) = self.ground {
+            offset[1] = ground - scale * bbox[1].min;
+        }
+        transform.then(&Transform::translate(&offset))
+    }
+}
+
 pub struct Model {
-    bvh: BVHNode,
+    mesh: TriangleMesh,
+    transform: Transform, // Placement applied to the model's vertices
 }
 
 impl Model {
-    pub fn new(model_filename: &str, scale: f64) -> Self {
-        Self::load(model_filename, None, scale)
+    pub fn new(
+        model_filename: &str,
+        options: &ModelOptions,
+        assets: &AssetResolver,
+    ) -> Result<Self, AssetError> {
+        Self::load(model_filename, None, options, assets)
     }
 
-    pub fn with_mat(model_filename: &str, mat: Arc<dyn Material>, scale: f64) -> Self {
-        Self::load(model_filename, Some(mat), scale)
+    pub fn with_mat(
+        model_filename: &str,
+        mat: Arc<dyn Material>,
+        options: &ModelOptions,
+        assets: &AssetResolver,
+    ) -> Result<Self, AssetError> {
+        Self::load(model_filename, Some(mat), options, assets)
     }
 
-    fn load(model_filename: &str, mat: Option<Arc<dyn Material>>, scale: f64) -> Self {
-        let filename = PathBuf::from(model_filename);
-        let modeldir = env::var("RTW_MODELS").ok();
+    #[allow(dead_code)] // Reported to callers, the built-in scenes don't need it
+    pub fn transform(&self) -> &Transform {
+        // The transform from the coordinates of the model file to the scene, also when the
+        // model is read from the cache.
+        &self.transform
+    }
 
-        let search_paths = vec![
-            modeldir.map(|dir| PathBuf::from(dir).join(&filename)),
-            Some(filename.clone()),
-            Some(PathBuf::from("models").join(&filename)),
-            Some(PathBuf::from("../models").join(&filename)),
-            Some(PathBuf::from("../../models").join(&filename)),
-            Some(PathBuf::from("../../../models").join(&filename)),
-            Some(PathBuf::from("../../../../models").join(&filename)),
-            Some(PathBuf::from("../../../../../models").join(&filename)),
-            Some(PathBuf::from("../../../../../../models").join(&filename)),
-        ];
+    fn load(
+        model_filename: &str,
+        mat: Option<Arc<dyn Material>>,
+        options: &ModelOptions,
+        assets: &AssetResolver,
+    ) -> Result<Self, AssetError> {
+        // Loads the first file found in the search paths. If it can't be loaded, the search
+        // stops there rather than picking up another file of the same name. With a cache
+        // directory, the placed mesh and its BVH are read from the cache file matching the
+        // model and its options, which is written on the first load.
+        let path = assets.resolve(model_filename, AssetKind::Model, None)?;
+        let Some(cache_dir) = assets.cache_dir() else {
+            return Self::load_file(&path, mat, options, assets).map(|(model, _)| model);
+        };
 
-        for path in search_paths.into_iter().flatten() {
-            if let Some(model) = Self::load_model(&path, mat.clone(), scale) {
-                return model;
-            }
+        let source = fs::read(&path).map_err(|e| AssetError::parse(&path, e))?;
+        let key = Self::cache_key(&path, &source, options, mat.is_some());
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let cache_path = cache_dir.join(format!("{stem}-{key:016x}.mesh"));
+        if let Some(model) = Self::read_cache(
+            &cache_path,
+            key,
+            &path,
+            &source,
+            mat.clone(),
+            options,
+            assets,
+        )? {
+            return Ok(model);
         }
 
-        panic!("ERROR: Could not load model file '{model_filename}'.");
+        let (model, source_bbox) = Self::load_file(&path, mat, options, assets)?;
+        let mut out = CacheWriter::new(key);
+        out.aabb(&source_bbox);
+        out.u8(model.mesh.has_vertex_colors() as u8);
+        model.mesh.write_cache(&mut out);
+        if let Err(e) = out.save(&cache_path) {
+            eprintln!(
+                "Could not write model cache '{}': {e}",
+                cache_path.display()
+            );
+        }
+        Ok(model)
     }
 
-    fn load_model(path: &PathBuf, mat: Option<Arc<dyn Material>>, scale: f64) -> Option<Self> {
-        if !path.exists() {
-            return None;
+    fn load_file(
+        path: &Path,
+        mat: Option<Arc<dyn Material>>,
+        options: &ModelOptions,
+        assets: &AssetResolver,
+    ) -> Result<(Self, AABB), AssetError> {
+        // Returns the model and its bounds in the coordinates of the file. The file format is
+        // chosen by the extension, OBJ being the default.
+        let mesh = match Self::extension(path).as_str() {
+            "ply" => Self::load_ply(path, mat)?,
+            "stl" => Self::load_stl(path, mat)?,
+            _ => Self::load_obj(path, mat, assets)?,
+        };
+
+        let source_bbox = mesh.bounding_box().clone();
+        let transform = options.transform(&source_bbox);
+        let model = Self {
+            mesh: mesh.transformed(&transform),
+            transform,
+        };
+        Ok((model, source_bbox))
+    }
+
+    fn extension(path: &Path) -> String {
+        let extension = path.extension().unwrap_or_default();
+        extension.to_string_lossy().to_ascii_lowercase()
+    }
+
+    fn cache_key(path: &Path, source: &[u8], options: &ModelOptions, material_given: bool) -> u64 {
+        // The cached mesh depends on the contents of the model file, on its placement, and on
+        // whether a material given by the caller replaces those of the file. Otherwise the
+        // material indices of an OBJ file depend on its MTL files, whose contents are hashed
+        // too, a missing file differing from an empty one.
+        let bits = |x: Option<f64>| x.map(f64::to_bits);
+        let mut hasher = Fnv1a::default();
+        hasher.write(source);
+        if !material_given && !matches!(Self::extension(path).as_str(), "ply" | "stl") {
+            for mtl in Self::mtllib_paths(path, source) {
+                fs::read(mtl).ok().hash(&mut hasher);
+            }
+        }
+        options.z_up.hash(&mut hasher);
+        options.scale.to_bits().hash(&mut hasher);
+        for axis in 0..3 {
+            bits(options.fit.as_ref().map(|fit| fit[axis])).hash(&mut hasher);
         }
+        bits(options.height).hash(&mut hasher);
+        options.center.hash(&mut hasher);
+        bits(options.ground).hash(&mut hasher);
+        material_given.hash(&mut hasher);
+        hasher.finish()
+    }
 
-        let (models, materials) = tobj::load_obj(
-            &path,
-            &tobj::LoadOptions {
-                triangulate: true,
-                ignore_lines: true,
-                ignore_points: true,
-                ..Default::default()
+    fn read_cache(
+        cache_path: &Path,
+        key: u64,
+        path: &Path,
+        source: &[u8],
+        mat: Option<Arc<dyn Material>>,
+        options: &ModelOptions,
+        assets: &AssetResolver,
+    ) -> Result<Option<Self>, AssetError> {
+        // Returns None if there is no cache file for the model. A cache file that can't be
+        // read is reported and ignored, the model is then loaded again. The materials aren't
+        // cached, they are made again from the model file.
+        let ignore = |e: io::Error| {
+            eprintln!("Ignoring model cache '{}': {e}", cache_path.display());
+            None
+        };
+        let mut input = match CacheReader::open(cache_path, key) {
+            Ok(Some(input)) => input,
+            Ok(None) => return Ok(None),
+            Err(e) => return Ok(ignore(e)),
+        };
+        let (source_bbox, vertex_colors) = match input.aabb().and_then(|bbox| {
+            let vertex_colors = input.u8()? != 0;
+            Ok((bbox, vertex_colors))
+        }) {
+            Ok(header) => header,
+            Err(e) => return Ok(ignore(e)),
+        };
+
+        let (materials, normal_maps) = match mat {
+            Some(mat) => (vec![mat], Vec::new()),
+            None => match Self::extension(path).as_str() {
+                "ply" => (vec![Self::ply_mat(vertex_colors)], Vec::new()),
+                "stl" => (vec![Self::default_mat()], Vec::new()),
+                _ => Self::obj_materials(Self::read_mtllibs(path, source), path, assets)?,
             },
+        };
+        let mesh = match TriangleMesh::read_cache(&mut input, materials, normal_maps)
+            .and_then(|mesh| input.finish().map(|_| mesh))
+        {
+            Ok(mesh) => mesh,
+            Err(e) => return Ok(ignore(e)),
+        };
+
+        Ok(Some(Self {
+            mesh,
+            transform: options.transform(&source_bbox),
+        }))
+    }
+
+    fn load_ply(path: &Path, mat: Option<Arc<dyn Material>>) -> Result<TriangleMesh, AssetError> {
+        let ply = ply::read_ply(path).map_err(|e| AssetError::parse(path, e))?;
+        if ply.triangles.is_empty() {
+            return Err(AssetError::EmptyMesh {
+                path: path.to_owned(),
+            });
+        }
+        if ply.normals.is_empty() {
+            eprintln!("Model has no normals, shading will be faceted.");
+        }
+
+        let mat = mat.unwrap_or_else(|| Self::ply_mat(!ply.colors.is_empty()));
+
+        let mesh = TriangleMesh::new(
+            ply.positions,
+            ply.normals,
+            ply.uvs,
+            ply.triangles,
+            vec![mat],
+            Vec::new(),
         )
-        .ok()?;
+        .with_vertex_colors(ply.colors);
+        Ok(mesh)
+    }
 
-        let mut default_mat: Arc<dyn Material> = Arc::new(Lambertian::from(Color::all(0.7843)));
-        let model_tex = if let Some(mat) = mat {
-            default_mat = mat;
-            None
+    fn load_stl(path: &Path, mat: Option<Arc<dyn Material>>) -> Result<TriangleMesh, AssetError> {
+        // STL has no vertex attributes besides positions, faces are shaded flat. Both the ASCII
+        // and binary variants are accepted.
+        let stl = File::open(path)
+            .and_then(|file| stl_io::read_stl(&mut BufReader::new(file)))
+            .map_err(|e| AssetError::parse(path, e))?;
+        if stl.faces.is_empty() {
+            return Err(AssetError::EmptyMesh {
+                path: path.to_owned(),
+            });
+        }
+
+        let positions = stl
+            .vertices
+            .iter()
+            .map(|v| Point::new(v[0] as f64, v[1] as f64, v[2] as f64))
+            .collect();
+        let triangles = stl
+            .faces
+            .iter()
+            .map(|f| f.vertices.map(|i| i as u32))
+            .collect();
+
+        let mat = mat.unwrap_or_else(Self::default_mat);
+        let mesh = TriangleMesh::new(
+            positions,
+            Vec::new(),
+            Vec::new(),
+            triangles,
+            vec![mat],
+            Vec::new(),
+        );
+        Ok(mesh)
+    }
+
+    fn default_mat() -> Arc<dyn Material> {
+        Arc::new(Lambertian::from(Color::all(0.7843)))
+    }
+
+    fn ply_mat(vertex_colors: bool) -> Arc<dyn Material> {
+        // Vertex colors scale the surface color, so they show as is on a white surface.
+        if vertex_colors {
+            Arc::new(Lambertian::from(Color::one()))
         } else {
-            if let Ok(mats) = materials {
-                let mat = &mats[0];
-                if let Some(map_kd) = &mat.diffuse_texture {
-                    Some(Arc::new(ImageTexture::new(map_kd)))
+            Self::default_mat()
+        }
+    }
+
+    fn load_obj(
+        path: &Path,
+        mat: Option<Arc<dyn Material>>,
+        assets: &AssetResolver,
+    ) -> Result<TriangleMesh, AssetError> {
+        let (models, materials) =
+            tobj::load_obj(path, &OBJ_LOAD_OPTIONS).map_err(|e| AssetError::parse(path, e))?;
+
+        // A material given by the caller replaces all of those of the file.
+        let (materials, normal_maps) = match mat {
+            Some(mat) => (vec![mat], Vec::new()),
+            None => Self::obj_materials(materials, path, assets)?,
+        };
+        let default_mat_id = materials.len() as u32 - 1;
+
+        // Every object is appended to a single mesh, its vertex indices shifted past the
+        // vertices of the previous objects. With single_index every vertex has one index shared
+        // by all its attributes. Attributes missing from some objects are zero-filled, a zero
+        // normal falls back to the geometric normal.
+        let has_normals = models.iter().any(|m| !m.mesh.normals.is_empty());
+        let has_uvs = models.iter().any(|m| !m.mesh.texcoords.is_empty());
+        if !has_normals {
+            eprintln!("Model has no normals, shading will be faceted.");
+        }
+
+        let mut positions = Vec::new();
+        let mut normals = Vec::new();
+        let mut uvs = Vec::new();
+        let mut triangles = Vec::new();
+        let mut material_ids = Vec::new();
+
+        for model in &models {
+            let mesh = &model.mesh;
+            let offset = positions.len() as u32;
+
+            positions.extend(
+                mesh.positions
+                    .chunks_exact(3)
+                    .map(|p| Point::new(p[0] as f64, p[1] as f64, p[2] as f64)),
+            );
+
+            if has_normals {
+                if mesh.normals.is_empty() {
+                    normals.resize(positions.len(), Vec3f64::zero());
                 } else {
-                    None
+                    normals.extend(
+                        mesh.normals
+                            .chunks_exact(3)
+                            .map(|n| Vec3f64::new(n[0] as f64, n[1] as f64, n[2] as f64)),
+                    );
                 }
-            } else {
-                None
             }
-        };
 
-        if models.len() > 1 {
-            eprintln!("Only use the first model and material.");
-        }
-        let mesh = &models[0].mesh;
-
-        let faces_len = mesh.indices.len() / 3;
-        let mut faces: Vec<Arc<dyn Hittable>> = Vec::with_capacity(faces_len);
-        for i in 0..faces_len {
-            let i_range = (3 * i)..(3 * i + 3);
-
-            let face_normal = if mesh.normal_indices.is_empty() {
-                eprintln!("Model has no normals.");
-                Vec3f64::new(0.0, 1.0, 0.0)
-            } else {
-                let normal_face_indices = &mesh.normal_indices[i_range.clone()];
-                let mut v = Vec3f64::new(
-                    mesh.normals[normal_face_indices[0] as usize] as f64,
-                    mesh.normals[normal_face_indices[1] as usize] as f64,
-                    mesh.normals[normal_face_indices[2] as usize] as f64,
-                );
-                if v.near_zero() {
-                    v = Vec3f64::new(0.0, 1.0, 0.0);
+            if has_uvs {
+                if mesh.texcoords.is_empty() {
+                    uvs.resize(positions.len(), (0.0, 0.0));
+                } else {
+                    uvs.extend(mesh.texcoords.chunks_exact(2).map(|uv| {
+                        let (mut u, mut v) = (uv[0] as f64, uv[1] as f64);
+                        if u < 0.0 {
+                            u += 1.0;
+                        }
+                        if v < 0.0 {
+                            v += 1.0;
+                        }
+                        (u, v)
+                    }));
                 }
-                v
-            };
-
-            let face_indices = &mesh.indices[i_range.clone()];
-            let face_pos: Vec<_> = face_indices
-                .iter()
-                .map(|i| {
-                    let i = 3 * *i as usize;
-                    Point::new(
-                        mesh.positions[i] as f64,
-                        mesh.positions[i + 1] as f64,
-                        mesh.positions[i + 2] as f64,
-                    )
-                })
-                .collect();
-
-            let mut a = &face_pos[1] - &face_pos[0];
-            let mut b = &face_pos[2] - &face_pos[0];
-            let should_swap = a.cross(&b).dot(&face_normal) < 0.0;
-            if should_swap {
-                std::mem::swap(&mut a, &mut b);
             }
 
-            let mat = if model_tex.is_some() && !mesh.texcoord_indices.is_empty() {
-                let texcoord_face_indices = &mesh.texcoord_indices[i_range.clone()];
-
-                let get_uv = |i: usize| {
-                    let uv_i = 2 * texcoord_face_indices[i] as usize;
-                    let mut uv = (mesh.texcoords[uv_i] as f64, mesh.texcoords[uv_i + 1] as f64);
-                    if uv.0 < 0.0 {
-                        uv.0 += 1.0;
-                    }
-                    if uv.1 < 0.0 {
-                        uv.1 += 1.0;
-                    }
-                    uv
-                };
-
-                let uv0 = get_uv(0);
-                let uv1 = get_uv(1);
-                let uv2 = get_uv(2);
-
-                let image_tex = model_tex.clone().unwrap();
-                let tex = if !should_swap {
-                    SubTexture::new(image_tex, uv0, uv1, uv2)
-                } else {
-                    SubTexture::new(image_tex, uv0, uv2, uv1)
-                };
-                Arc::new(Lambertian::new(Arc::new(tex)))
-            } else {
-                default_mat.clone()
+            let mat_id = match mesh.material_id {
+                Some(id) if (id as u32) < default_mat_id => id as u32,
+                _ => default_mat_id,
             };
+            let triangle_count = mesh.indices.len() / 3;
+            triangles.extend(
+                mesh.indices
+                    .chunks_exact(3)
+                    .map(|f| [f[0] + offset, f[1] + offset, f[2] + offset]),
+            );
+            material_ids.resize(material_ids.len() + triangle_count, mat_id);
+        }
 
-            faces.push(Arc::new(Quad::with_shape(
-                &face_pos[0] * scale,
-                a * scale,
-                b * scale,
-                mat,
-                Shape2D::Triangle,
-            )));
+        if triangles.is_empty() {
+            return Err(AssetError::EmptyMesh {
+                path: path.to_owned(),
+            });
         }
 
-        Some(Self {
-            bvh: BVHNode::from(faces),
+        Ok(
+            TriangleMesh::new(positions, normals, uvs, triangles, materials, material_ids)
+                .with_normal_maps(normal_maps),
+        )
+    }
+
+    fn obj_materials(
+        materials: Result<Vec<tobj::Material>, tobj::LoadError>,
+        path: &Path,
+        assets: &AssetResolver,
+    ) -> Result<MaterialTable, AssetError> {
+        // The material table holds one entry per MTL material, followed by the default material
+        // for objects without one.
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let mut entries = materials
+            .unwrap_or_else(|e| {
+                eprintln!("Could not load the materials of '{}': {e}", path.display());
+                Vec::new()
+            })
+            .iter()
+            .map(|m| Self::convert_material(m, dir, assets))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.for_texture_of(path))?;
+        entries.push((Self::default_mat(), None));
+        Ok(entries.into_iter().unzip())
+    }
+
+    fn mtllib_lines(source: &[u8]) -> impl Iterator<Item = &[u8]> {
+        source
+            .split(|&b| b == b'\n')
+            .filter(|line| line.trim_ascii_start().starts_with(b"mtllib"))
+    }
+
+    fn mtllib_paths(path: &Path, source: &[u8]) -> Vec<PathBuf> {
+        // The MTL files named by the mtllib statements of an OBJ file, each taking the rest of
+        // its line after the first space as tobj does.
+        let dir = path.parent().unwrap_or(Path::new(""));
+        Self::mtllib_lines(source)
+            .map(|line| String::from_utf8_lossy(line))
+            .filter_map(|line| Some(dir.join(line.split_once(' ')?.1.trim())))
+            .collect()
+    }
+
+    fn read_mtllibs(path: &Path, source: &[u8]) -> Result<Vec<tobj::Material>, tobj::LoadError> {
+        // Loads the MTL files of an OBJ file without parsing its geometry, by handing tobj only
+        // the mtllib statements, so that the materials come out as when loading the whole file.
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let mtllibs: Vec<u8> = Self::mtllib_lines(source)
+            .flat_map(|line| line.iter().chain(b"\n"))
+            .copied()
+            .collect();
+        tobj::load_obj_buf(&mut mtllibs.as_slice(), &OBJ_LOAD_OPTIONS, |mtl| {
+            tobj::load_mtl(dir.join(mtl))
         })
+        .and_then(|(_, materials)| materials)
+    }
+
+    fn convert_material(
+        m: &tobj::Material,
+        dir: &Path,
+        assets: &AssetResolver,
+    ) -> Result<MaterialEntry, AssetError> {
+        // Maps MTL properties to the closest material of the renderer:
+        // - Ke/map_Ke: emission, as a diffuse light
+        // - d < 1 or a transparent illumination model: a dielectric with index Ni, tinted by Tf
+        // - Ks brighter than Kd or a reflective illumination model: a metal of color Ks, whose
+        //   fuzz decreases with the specular exponent Ns
+        // - Otherwise Kd/map_Kd: a Lambertian surface
+        // Normal maps come from map_Bump, bump or norm.
+        let peak = |c: &Color| c[0].max(c[1]).max(c[2]);
+        let color = |c: [f32; 3]| Color::new(c[0] as f64, c[1] as f64, c[2] as f64);
+        let image = |spec: &str| assets.image(Self::texture_file(spec), Some(dir));
+        let texture = |spec: &str| image(spec).map(|i| Arc::new(ImageTexture::from_image(i)));
+
+        let diffuse = m.diffuse.map(color).unwrap_or(Color::all(0.7843));
+        let specular = m.specular.map(color).unwrap_or(Color::zero());
+        let emission = Self::param_color(m, "Ke").unwrap_or(Color::zero());
+        let illum = m.illumination_model.unwrap_or(2);
+
+        let mat: Arc<dyn Material> = if let Some(map_ke) = m.unknown_param.get("map_Ke") {
+            Arc::new(DiffuseLight::new(texture(map_ke)?))
+        } else if peak(&emission) > 0.0 {
+            Arc::new(DiffuseLight::from(emission))
+        } else if m.dissolve.is_some_and(|d| d < 1.0) || matches!(illum, 4 | 6 | 7 | 9) {
+            let tint = Self::param_color(m, "Tf").unwrap_or(Color::one());
+            Arc::new(Dielectric::with_tint(
+                m.optical_density.unwrap_or(1.5) as f64,
+                tint,
+            ))
+        } else if matches!(illum, 3 | 5) || peak(&specular) > peak(&diffuse) {
+            let shininess = m.shininess.unwrap_or(0.0).max(0.0) as f64;
+            let fuzz = (2.0 / (shininess + 2.0)).sqrt();
+            Arc::new(Metal::new(specular, fuzz))
+        } else if let Some(map_kd) = &m.diffuse_texture {
+            Arc::new(Lambertian::new(texture(map_kd)?))
+        } else {
+            Arc::new(Lambertian::from(diffuse))
+        };
+
+        let normal_map = match m.normal_texture.as_ref().or(m.unknown_param.get("norm")) {
+            Some(spec) => Some(Arc::new(NormalMap::from_image(image(spec)?))),
+            None => None,
+        };
+
+        Ok((mat, normal_map))
+    }
+
+    fn param_color(m: &tobj::Material, key: &str) -> Option<Color> {
+        // Parses a color parameter tobj doesn't know about, given as one or three values.
+        let values = m.unknown_param.get(key)?;
+        let values: Vec<f64> = values
+            .split_whitespace()
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .ok()?;
+        match values[..] {
+            [c] => Some(Color::all(c)),
+            [r, g, b] => Some(Color::new(r, g, b)),
+            _ => None,
+        }
+    }
+
+    fn texture_file(spec: &str) -> &str {
+        // Texture statements may carry options before the file name, e.g. "-bm 0.5 normal.png".
+        // Texture files are looked up next to the model first, then like any other image.
+        spec.split_whitespace().last().unwrap_or(spec)
     }
 }
 
 impl Hittable for Model {
-    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
-        self.bvh.hit(r, ray_t)
+    fn closest_hit(&self, r: &Ray, ray_t: Interval) -> Option<Hit> {
+        self.mesh.closest_hit(r, ray_t)
+    }
+
+    fn interaction(&self, r: &Ray, hit: &mut Hit) -> HitRecord {
+        self.mesh.interaction(r, hit)
+    }
+
+    fn surface_point(&self, r: &Ray, hit: &mut Hit) -> SurfacePoint {
+        self.mesh.surface_point(r, hit)
+    }
+
+    fn occluded(&self, r: &Ray, ray_t: Interval) -> bool {
+        self.mesh.occluded(r, ray_t)
     }
 
     fn bounding_box(&self) -> &AABB {
-        self.bvh.bounding_box()
+        self.mesh.bounding_box()
     }
 }
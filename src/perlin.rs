@@ -69,6 +69,7 @@ impl Perlin {
         }
     }
 
+    #[allow(clippy::needless_range_loop)]
     fn perlin_interp(c: &[[[Vec3f64; 2]; 2]; 2], u: f64, v: f64, w: f64) -> f64 {
         let uu = u * u * (3.0 - 2.0 * u);
         let vv = v * v * (3.0 - 2.0 * v);
@@ -0,0 +1,503 @@
+use crate::color::Color;
+use crate::triangle::UV;
+use crate::vec3::{Point, Vec3f64};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+/// Triangle mesh read from a PLY file.
+///
+/// Vertex attributes other than the position are empty if the file doesn't have them. Polygons
+/// with more than three vertices are split into triangle fans.
+#[derive(Default)]
+pub struct PlyMesh {
+    pub positions: Vec<Point>,
+    pub normals: Vec<Vec3f64>,
+    pub uvs: Vec<UV>,
+    pub colors: Vec<Color>,
+    pub triangles: Vec<[u32; 3]>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy)]
+enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+enum PropertyType {
+    Scalar(ScalarType),
+    List { count: ScalarType, item: ScalarType },
+}
+
+struct Property {
+    name: String,
+    kind: PropertyType,
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> io::Result<Self> {
+        match name {
+            "char" | "int8" => Ok(Self::I8),
+            "uchar" | "uint8" => Ok(Self::U8),
+            "short" | "int16" => Ok(Self::I16),
+            "ushort" | "uint16" => Ok(Self::U16),
+            "int" | "int32" => Ok(Self::I32),
+            "uint" | "uint32" => Ok(Self::U32),
+            "float" | "float32" => Ok(Self::F32),
+            "double" | "float64" => Ok(Self::F64),
+            _ => Err(invalid(format!("unknown PLY property type '{name}'"))),
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Self::I8 | Self::U8 => 1,
+            Self::I16 | Self::U16 => 2,
+            Self::I32 | Self::U32 | Self::F32 => 4,
+            Self::F64 => 8,
+        }
+    }
+
+    fn is_integer(self) -> bool {
+        !matches!(self, Self::F32 | Self::F64)
+    }
+}
+
+// Source of property values, either whitespace separated tokens or packed binary scalars.
+struct ValueReader<R> {
+    reader: R,
+    format: Format,
+    tokens: Vec<String>, // Remaining tokens of the current line, in reverse order
+}
+
+impl<R: BufRead> ValueReader<R> {
+    fn read_property(&mut self, kind: &PropertyType) -> io::Result<f64> {
+        // Reads a scalar property, or skips a list property and returns 0.
+        match *kind {
+            PropertyType::Scalar(kind) => self.read(kind),
+            PropertyType::List { count, item } => {
+                for _ in 0..self.read(count)? as usize {
+                    self.read(item)?;
+                }
+                Ok(0.0)
+            }
+        }
+    }
+
+    fn read(&mut self, kind: ScalarType) -> io::Result<f64> {
+        if self.format == Format::Ascii {
+            while self.tokens.is_empty() {
+                let mut line = String::new();
+                if self.reader.read_line(&mut line)? == 0 {
+                    return Err(invalid("unexpected end of PLY data"));
+                }
+                self.tokens = line.split_whitespace().rev().map(str::to_owned).collect();
+            }
+            let token = self.tokens.pop().unwrap_or_default();
+            return token
+                .parse()
+                .map_err(|_| invalid(format!("invalid PLY value '{token}'")));
+        }
+
+        let mut bytes = [0; 8];
+        let bytes = &mut bytes[..kind.size()];
+        self.reader.read_exact(bytes)?;
+        if self.format == Format::BinaryBigEndian {
+            bytes.reverse();
+        }
+
+        Ok(match kind {
+            ScalarType::I8 => bytes[0] as i8 as f64,
+            ScalarType::U8 => bytes[0] as f64,
+            ScalarType::I16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            ScalarType::U16 => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            ScalarType::I32 => i32::from_le_bytes(bytes.try_into().unwrap()) as f64,
+            ScalarType::U32 => u32::from_le_bytes(bytes.try_into().unwrap()) as f64,
+            ScalarType::F32 => f32::from_le_bytes(bytes.try_into().unwrap()) as f64,
+            ScalarType::F64 => f64::from_le_bytes(bytes.try_into().unwrap()),
+        })
+    }
+}
+
+pub fn read_ply(path: &Path) -> io::Result<PlyMesh> {
+    parse_ply(BufReader::new(File::open(path)?))
+}
+
+fn parse_ply(mut reader: impl BufRead) -> io::Result<PlyMesh> {
+    let (format, elements) = read_header(&mut reader)?;
+    let mut values = ValueReader {
+        reader,
+        format,
+        tokens: Vec::new(),
+    };
+
+    let mut mesh = PlyMesh::default();
+    for element in &elements {
+        match element.name.as_str() {
+            "vertex" => read_vertices(&mut values, element, &mut mesh)?,
+            "face" => read_faces(&mut values, element, &mut mesh)?,
+            _ => skip_element(&mut values, element)?,
+        }
+    }
+
+    let vertex_count = mesh.positions.len() as u32;
+    if mesh.triangles.iter().flatten().any(|&i| i >= vertex_count) {
+        return Err(invalid("PLY face refers to a missing vertex"));
+    }
+
+    Ok(mesh)
+}
+
+fn read_header(reader: &mut impl BufRead) -> io::Result<(Format, Vec<Element>)> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if line.trim() != "ply" {
+        return Err(invalid("not a PLY file"));
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid("unexpected end of PLY header"));
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        match words[..] {
+            ["end_header"] => break,
+            ["format", "ascii", _] => format = Some(Format::Ascii),
+            ["format", "binary_little_endian", _] => format = Some(Format::BinaryLittleEndian),
+            ["format", "binary_big_endian", _] => format = Some(Format::BinaryBigEndian),
+            ["element", name, count] => elements.push(Element {
+                name: name.to_owned(),
+                count: count
+                    .parse()
+                    .map_err(|_| invalid(format!("invalid PLY element count '{count}'")))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => {
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| invalid("PLY property outside of an element"))?;
+                element.properties.push(Property {
+                    name: name.to_owned(),
+                    kind: PropertyType::List {
+                        count: ScalarType::parse(count)?,
+                        item: ScalarType::parse(item)?,
+                    },
+                });
+            }
+            ["property", kind, name] => {
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| invalid("PLY property outside of an element"))?;
+                element.properties.push(Property {
+                    name: name.to_owned(),
+                    kind: PropertyType::Scalar(ScalarType::parse(kind)?),
+                });
+            }
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            _ => {
+                return Err(invalid(format!(
+                    "invalid PLY header line '{}'",
+                    line.trim()
+                )));
+            }
+        }
+    }
+
+    let format = format.ok_or_else(|| invalid("PLY header has no format"))?;
+    Ok((format, elements))
+}
+
+fn read_vertices(
+    values: &mut ValueReader<impl BufRead>,
+    element: &Element,
+    mesh: &mut PlyMesh,
+) -> io::Result<()> {
+    // Attributes are picked by property name. Texture coordinates go by several names, and
+    // integer colors are scaled from [0, 255] to [0, 1]. Colors are stored as gamma 2, like the
+    // colors of image textures.
+    let index = |names: &[&str]| {
+        element
+            .properties
+            .iter()
+            .position(|p| names.contains(&p.name.as_str()))
+    };
+    let xyz = [index(&["x"]), index(&["y"]), index(&["z"])];
+    let nxyz = [index(&["nx"]), index(&["ny"]), index(&["nz"])];
+    let uv = [
+        index(&["u", "s", "texture_u", "texture_s"]),
+        index(&["v", "t", "texture_v", "texture_t"]),
+    ];
+    let rgb = [
+        index(&["red", "r", "diffuse_red"]),
+        index(&["green", "g", "diffuse_green"]),
+        index(&["blue", "b", "diffuse_blue"]),
+    ];
+
+    let [Some(x), Some(y), Some(z)] = xyz else {
+        return Err(invalid("PLY vertices have no position"));
+    };
+    let normal = match nxyz {
+        [Some(nx), Some(ny), Some(nz)] => Some([nx, ny, nz]),
+        _ => None,
+    };
+    let uv = match uv {
+        [Some(u), Some(v)] => Some([u, v]),
+        _ => None,
+    };
+    let color = match rgb {
+        [Some(r), Some(g), Some(b)] => Some([r, g, b]),
+        _ => None,
+    };
+    let color_scale = match color.map(|c| &element.properties[c[0]].kind) {
+        Some(PropertyType::Scalar(kind)) if kind.is_integer() => 1.0 / 255.0,
+        _ => 1.0,
+    };
+
+    let mut row = vec![0.0; element.properties.len()];
+    for _ in 0..element.count {
+        for (property, value) in element.properties.iter().zip(row.iter_mut()) {
+            *value = values.read_property(&property.kind)?;
+        }
+
+        mesh.positions.push(Point::new(row[x], row[y], row[z]));
+        if let Some([nx, ny, nz]) = normal {
+            mesh.normals.push(Vec3f64::new(row[nx], row[ny], row[nz]));
+        }
+        if let Some([u, v]) = uv {
+            mesh.uvs.push((row[u], row[v]));
+        }
+        if let Some([r, g, b]) = color {
+            let c = Color::new(row[r], row[g], row[b]) * color_scale;
+            mesh.colors.push(&c * &c);
+        }
+    }
+
+    Ok(())
+}
+
+fn read_faces(
+    values: &mut ValueReader<impl BufRead>,
+    element: &Element,
+    mesh: &mut PlyMesh,
+) -> io::Result<()> {
+    let mut polygon = Vec::new();
+    for _ in 0..element.count {
+        for property in &element.properties {
+            match property.kind {
+                PropertyType::List { count, item }
+                    if matches!(property.name.as_str(), "vertex_indices" | "vertex_index") =>
+                {
+                    polygon.clear();
+                    for _ in 0..values.read(count)? as usize {
+                        let index = values.read(item)?;
+                        if !(index >= 0.0 && index <= u32::MAX as f64 && index.fract() == 0.0) {
+                            return Err(invalid(format!("invalid PLY vertex index {index}")));
+                        }
+                        polygon.push(index as u32);
+                    }
+                    for i in 1..polygon.len().saturating_sub(1) {
+                        mesh.triangles
+                            .push([polygon[0], polygon[i], polygon[i + 1]]);
+                    }
+                }
+                _ => {
+                    values.read_property(&property.kind)?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn skip_element(values: &mut ValueReader<impl BufRead>, element: &Element) -> io::Result<()> {
+    for _ in 0..element.count {
+        for property in &element.properties {
+            values.read_property(&property.kind)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(data: &[u8]) -> io::Result<PlyMesh> {
+        parse_ply(data)
+    }
+
+    fn ordered<const N: usize>(mut le_bytes: [u8; N], big_endian: bool) -> [u8; N] {
+        if big_endian {
+            le_bytes.reverse();
+        }
+        le_bytes
+    }
+
+    fn ascii(vertices: &str, faces: &str) -> Vec<u8> {
+        // A PLY file of three vertices, then the given faces, with a list of ints per face.
+        let face_count = faces.lines().count();
+        format!(
+            "ply\nformat ascii 1.0\ncomment test\nelement vertex 3\nproperty float x\n\
+             property float y\nproperty float z\nelement face {face_count}\n\
+             property list uchar int vertex_indices\nend_header\n{vertices}{faces}"
+        )
+        .into_bytes()
+    }
+
+    fn binary(format: &str, big_endian: bool) -> Vec<u8> {
+        // A PLY file of four vertices with uchar colors, an unknown element that must be
+        // skipped, and a quad.
+        let mut data = format!(
+            "ply\nformat {format} 1.0\nelement vertex 4\nproperty float x\nproperty float y\n\
+             property float z\nproperty uchar red\nproperty uchar green\nproperty uchar blue\n\
+             element edge 1\nproperty short vertex1\nproperty list uchar short extra\n\
+             element face 1\nproperty list uchar uint vertex_indices\nend_header\n"
+        )
+        .into_bytes();
+        let corners = [
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [0.0, 1.0, 0.0],
+        ];
+        for (i, corner) in corners.iter().enumerate() {
+            for &x in corner {
+                data.extend(ordered(f32::to_le_bytes(x), big_endian));
+            }
+            data.extend([255, 0, 51 * i as u8]);
+        }
+        data.extend(ordered((-7i16).to_le_bytes(), big_endian));
+        data.push(2);
+        data.extend(ordered(1i16.to_le_bytes(), big_endian));
+        data.extend(ordered(2i16.to_le_bytes(), big_endian));
+        data.push(4);
+        for i in 0u32..4 {
+            data.extend(ordered(i.to_le_bytes(), big_endian));
+        }
+        data
+    }
+
+    fn check_quad(mesh: &PlyMesh) {
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.positions[2], Point::new(1.0, 1.0, 0.0));
+        assert_eq!(mesh.triangles, [[0, 1, 2], [0, 2, 3]]);
+        assert!(mesh.normals.is_empty() && mesh.uvs.is_empty());
+        // Colors are scaled to [0, 1] and squared.
+        assert_eq!(mesh.colors.len(), 4);
+        assert_eq!(mesh.colors[3], Color::new(1.0, 0.0, 0.36));
+    }
+
+    #[test]
+    fn ascii_triangle() {
+        let mesh = parse(&ascii("0 0 0\n1 0 0\n0 1 0\n", "3 0 1 2\n")).unwrap();
+        assert_eq!(mesh.positions.len(), 3);
+        assert_eq!(mesh.positions[1], Point::new(1.0, 0.0, 0.0));
+        assert_eq!(mesh.triangles, [[0, 1, 2]]);
+        assert!(mesh.colors.is_empty());
+    }
+
+    #[test]
+    fn ascii_attributes_and_polygons() {
+        // Extra face properties are skipped, and attributes found by their other names.
+        let data = b"ply
+format ascii 1.0
+element vertex 5
+property double x
+property double y
+property double z
+property float nx
+property float ny
+property float nz
+property float s
+property float t
+property float red
+property float green
+property float blue
+element face 2
+property uchar flags
+property list uchar int vertex_index
+end_header
+0 0 0 0 0 1 0 0 0.5 0.5 0.5
+1 0 0 0 0 1 1 0 0.5 0.5 0.5
+1 1 0 0 0 1 1 1 0.5 0.5 0.5
+0.5 2 0 0 0 1 0.5 1 0.5 0.5 0.5
+0 1 0 0 0 1 0 1 1 0 0.5
+7 5 0 1 2 3 4
+1 2 4 3
+";
+        let mesh = parse(data).unwrap();
+        assert_eq!(mesh.positions[3], Point::new(0.5, 2.0, 0.0));
+        assert_eq!(mesh.normals[4], Vec3f64::new(0.0, 0.0, 1.0));
+        assert_eq!(mesh.uvs[2], (1.0, 1.0));
+        // Float colors are not scaled, but squared like integer ones.
+        assert_eq!(mesh.colors[4], Color::new(1.0, 0.0, 0.25));
+        // A pentagon becomes a fan of three triangles, a two-vertex face none.
+        assert_eq!(mesh.triangles, [[0, 1, 2], [0, 2, 3], [0, 3, 4]]);
+    }
+
+    #[test]
+    fn binary_little_endian() {
+        check_quad(&parse(&binary("binary_little_endian", false)).unwrap());
+    }
+
+    #[test]
+    fn binary_big_endian() {
+        check_quad(&parse(&binary("binary_big_endian", true)).unwrap());
+    }
+
+    #[test]
+    fn truncated_binary_data() {
+        let data = binary("binary_little_endian", false);
+        assert!(parse(&data[..data.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn invalid_vertex_indices() {
+        let vertices = "0 0 0\n1 0 0\n0 1 0\n";
+        // Past the last vertex, negative, fractional and not a number.
+        for face in ["3 0 1 3\n", "3 0 -1 2\n", "3 0 1.5 2\n", "3 0 x 2\n"] {
+            assert!(parse(&ascii(vertices, face)).is_err(), "{face}");
+        }
+    }
+
+    #[test]
+    fn invalid_headers() {
+        assert!(parse(b"plx\nformat ascii 1.0\nend_header\n").is_err());
+        assert!(parse(b"ply\nelement vertex 0\nend_header\n").is_err());
+        assert!(parse(b"ply\nformat ascii 1.0\nproperty float x\nend_header\n").is_err());
+        assert!(parse(b"ply\nformat ascii 1.0\nelement vertex 1\nproperty half x\n").is_err());
+        assert!(parse(b"ply\nformat ascii 1.0\nelement vertex 0\n").is_err());
+        let no_position =
+            b"ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nend_header\n1\n";
+        assert!(parse(no_position).is_err());
+    }
+}
@@ -1,8 +1,9 @@
 use crate::aabb::AABB;
-use crate::hittable::{HitRecord, Hittable};
+use crate::hittable::{Hit, HitRecord, Hittable, SurfacePoint};
 use crate::interval::Interval;
 use crate::material::Material;
 use crate::ray::Ray;
+use crate::rtweekend::gamma;
 use crate::vec3::{Point, Vec3f64};
 use std::sync::Arc;
 
@@ -63,6 +64,7 @@ pub struct Quad {
     normal: Vec3f64,
     d: f64,
     contains_fn: Shape2DFn,
+    uv_scale: f64, // Plane coordinates per unit of texture coordinate
 }
 
 impl Quad {
@@ -94,6 +96,10 @@ impl Quad {
         let w = n.clone() / n.dot(&n);
         let normal = u.cross(&v).into_unit_vector();
         let d = normal.dot(&q);
+        let uv_scale = match shape {
+            Shape2D::Parallelogram | Shape2D::Triangle => 1.0,
+            Shape2D::Circle | Shape2D::Ellipse | Shape2D::Annulus { .. } => 2.0,
+        };
         Self {
             q,
             u,
@@ -104,6 +110,7 @@ impl Quad {
             normal,
             d,
             contains_fn: shape.into(),
+            uv_scale,
         }
     }
 
@@ -163,10 +170,9 @@ impl Quad {
 
         sides
     }
-}
 
-impl Hittable for Quad {
-    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+    fn intersect(&self, r: &Ray, ray_t: Interval) -> Option<(f64, f64, f64)> {
+        // Returns the ray parameter t of the hit and its plane coordinates.
         let denom = self.normal.dot(r.direction());
 
         // No hit if the ray is parallel to the plane.
@@ -181,21 +187,48 @@ impl Hittable for Quad {
         }
 
         // Determine if the hit point lies within the planar shape using its plane coordinates.
-        let intersection = r.at(t);
-        let planar_hitpt_vector = &intersection - &self.q;
+        let planar_hitpt_vector = r.at(t) - &self.q;
         let alpha = self.w.dot(&planar_hitpt_vector.cross(&self.v));
         let beta = self.w.dot(&self.u.cross(&planar_hitpt_vector));
 
-        let uv = (self.contains_fn)(alpha, beta)?;
+        (self.contains_fn)(alpha, beta)?;
+        Some((t, alpha, beta))
+    }
+}
+
+impl Hittable for Quad {
+    fn closest_hit(&self, r: &Ray, ray_t: Interval) -> Option<Hit> {
+        let (t, alpha, beta) = self.intersect(r, ray_t)?;
+        Some(Hit::with_coords(t, (alpha, beta)))
+    }
+
+    fn interaction(&self, r: &Ray, hit: &mut Hit) -> HitRecord {
+        // The plane coordinates passed the shape test in closest_hit, which gives the same
+        // texture coordinates again.
+        let (alpha, beta) = hit.coords;
+        let uv = (self.contains_fn)(alpha, beta).unwrap_or_default();
+        let point = self.surface_point(r, hit);
+
+        HitRecord::new(r, point.t, point.p, point.normal, self.mat.clone(), uv)
+            .with_p_error(point.p_error)
+            .with_dpduv(&self.u * self.uv_scale, &self.v * self.uv_scale)
+    }
+
+    fn surface_point(&self, _r: &Ray, hit: &mut Hit) -> SurfacePoint {
+        // The hit point is rebuilt from its plane coordinates, which keeps it on the plane up to
+        // the rounding of the sum, however far along the ray it is.
+        let (alpha, beta) = hit.coords;
+        let (du, dv) = (&self.u * alpha, &self.v * beta);
+        SurfacePoint {
+            t: hit.t,
+            p: &self.q + &du + &dv,
+            p_error: (self.q.abs() + du.abs() + dv.abs()) * gamma(3),
+            normal: self.normal.clone(),
+        }
+    }
 
-        Some(HitRecord::new(
-            r,
-            t,
-            intersection,
-            self.normal.clone(),
-            self.mat.clone(),
-            uv,
-        ))
+    fn occluded(&self, r: &Ray, ray_t: Interval) -> bool {
+        self.intersect(r, ray_t).is_some()
     }
 
     fn bounding_box(&self) -> &AABB {
@@ -1,9 +1,23 @@
 use crate::vec3::{Point, Vec3f64};
 
+/// Offset rays through the neighbouring pixel samples, one step along the image x and y axes.
+///
+/// They follow the main ray through specular bounces, so that the spread between them at a hit
+/// point gives the footprint of a pixel on the surface.
+#[derive(Clone)]
+pub struct RayDifferential {
+    pub rx_origin: Point,
+    pub rx_direction: Vec3f64,
+    pub ry_origin: Point,
+    pub ry_direction: Vec3f64,
+}
+
 pub struct Ray {
     orig: Point,
     dir: Vec3f64,
     tm: f64,
+    inv_dir: Vec3f64, // Componentwise inverse of the direction, for box intersections
+    differential: Option<RayDifferential>, // Unknown past diffuse bounces
 }
 
 impl Ray {
@@ -12,13 +26,21 @@ impl Ray {
     }
 
     pub fn with_time(origin: Point, direction: Vec3f64, time: f64) -> Self {
+        let inv_dir = Vec3f64::new(1.0 / direction[0], 1.0 / direction[1], 1.0 / direction[2]);
         Self {
             orig: origin,
             dir: direction,
             tm: time,
+            inv_dir,
+            differential: None,
         }
     }
 
+    pub fn with_differential(mut self, differential: Option<RayDifferential>) -> Self {
+        self.differential = differential;
+        self
+    }
+
     pub fn origin(&self) -> &Point {
         &self.orig
     }
@@ -27,6 +49,14 @@ impl Ray {
         &self.dir
     }
 
+    pub fn inv_direction(&self) -> &Vec3f64 {
+        &self.inv_dir
+    }
+
+    pub fn differential(&self) -> Option<&RayDifferential> {
+        self.differential.as_ref()
+    }
+
     pub fn time(&self) -> f64 {
         self.tm
     }
@@ -1,3 +1,14 @@
 pub fn degrees_to_radians(degrees: f64) -> f64 {
     degrees * std::f64::consts::PI / 180.0
-}
\ No newline at end of file
+}
+
+pub fn radians_to_degrees(radians: f64) -> f64 {
+    radians * 180.0 / std::f64::consts::PI
+}
+
+pub fn gamma(n: u32) -> f64 {
+    // Bound on the relative error accumulated by n floating-point operations, each rounding to
+    // within half an ulp.
+    let e = n as f64 * f64::EPSILON * 0.5;
+    e / (1.0 - e)
+}
@@ -1,70 +1,82 @@
+use crate::asset::AssetError;
 use image::ImageReader;
-use std::env;
-use std::path::PathBuf;
+use std::path::Path;
+use std::sync::OnceLock;
 
 pub struct RtwImage {
-    width: u32,             // Loaded image width
-    height: u32,            // Loaded image height
-    bdata: Option<Vec<u8>>, // Linear 8-bit pixel data
+    width: u32,                          // Loaded image width
+    height: u32,                         // Loaded image height
+    bdata: Option<Vec<u8>>,              // Linear 8-bit pixel data
+    mip_levels: OnceLock<Vec<MipLevel>>, // Downsampled copies, built on first use
 }
 
-impl RtwImage {
-    pub fn new(image_filename: &str) -> Self {
-        // Loads image data from the specified file. If the RTW_IMAGES environment variable is
-        // defined, looks only in that directory for the image file. If the image was not found,
-        // searches for the specified image file first from the current directory, then in the
-        // images/ subdirectory, then the _parent's_ images/ subdirectory, and then _that_
-        // parent, on so on, for six levels up. If the image was not loaded successfully,
-        // width() and height() will return 0.
-
-        let filename = PathBuf::from(image_filename);
-        let imagedir = env::var("RTW_IMAGES").ok();
-
-        // Hunt for the image file in some likely locations.
-        let search_paths = vec![
-            imagedir.map(|dir| PathBuf::from(dir).join(&filename)),
-            Some(filename.clone()),
-            Some(PathBuf::from("images").join(&filename)),
-            Some(PathBuf::from("../images").join(&filename)),
-            Some(PathBuf::from("../../images").join(&filename)),
-            Some(PathBuf::from("../../../images").join(&filename)),
-            Some(PathBuf::from("../../../../images").join(&filename)),
-            Some(PathBuf::from("../../../../../images").join(&filename)),
-            Some(PathBuf::from("../../../../../../images").join(&filename)),
-        ];
-
-        for path in search_paths.into_iter().flatten() {
-            if let Some(image) = Self::load(&path) {
-                return image;
+// One level of the mip map of an image, half the size of the previous one.
+struct MipLevel {
+    width: u32,
+    height: u32,
+    bdata: Vec<u8>,
+}
+
+impl MipLevel {
+    fn downsample(width: u32, height: u32, bdata: &[u8]) -> Self {
+        // Averages each 2x2 block of texels. Odd sizes repeat the last row or column.
+        let (w, h) = (width.div_ceil(2), height.div_ceil(2));
+        let mut out = Vec::with_capacity((w * h * 3) as usize);
+        for y in 0..h {
+            let (y0, y1) = ((2 * y).min(height - 1), (2 * y + 1).min(height - 1));
+            for x in 0..w {
+                let (x0, x1) = ((2 * x).min(width - 1), (2 * x + 1).min(width - 1));
+                for c in 0..3 {
+                    let texel = |x: u32, y: u32| bdata[((y * width + x) * 3 + c) as usize] as u32;
+                    let sum = texel(x0, y0) + texel(x1, y0) + texel(x0, y1) + texel(x1, y1);
+                    out.push(((sum + 2) / 4) as u8);
+                }
             }
         }
-
-        panic!("ERROR: Could not load image file '{image_filename}'.");
+        Self {
+            width: w,
+            height: h,
+            bdata: out,
+        }
     }
+}
 
-    fn load(path: &PathBuf) -> Option<Self> {
-        // Loads the linear (gamma=1) image data from the given file name. Returns true if the
-        // load succeeded. The resulting data buffer contains the three [0.0, 1.0]
+impl RtwImage {
+    pub fn new(path: &Path) -> Result<Self, AssetError> {
+        // Loads the linear (gamma=1) image data from the given file. Fails if the file can't be
+        // read or decoded. Files are looked up by the AssetResolver, which also keeps the loaded
+        // images. The resulting data buffer contains the three [0.0, 1.0]
         // floating-point values for the first pixel (red, then green, then blue). Pixels are
         // contiguous, going left to right for the width of the image, followed by the next row
         // below, for the full height of the image.
 
-        if !path.exists() {
-            return None;
-        }
-
-        let img = ImageReader::open(path).ok()?.decode().ok()?;
+        let img = ImageReader::open(path)
+            .map_err(|e| AssetError::parse(path, e))?
+            .decode()
+            .map_err(|e| AssetError::parse(path, e))?;
         let rgb_image = img.to_rgb8();
         let (width, height) = rgb_image.dimensions();
         let bdata = rgb_image.into_raw();
 
-        Some(Self {
+        Ok(Self {
             width,
             height,
             bdata: Some(bdata),
+            mip_levels: OnceLock::new(),
         })
     }
 
+    pub fn from_rgb8(width: u32, height: u32, bdata: Vec<u8>) -> Self {
+        // Wraps already decoded pixel data, e.g. an image embedded in a glTF file.
+        assert_eq!(bdata.len(), (width * height * 3) as usize);
+        Self {
+            width,
+            height,
+            bdata: Some(bdata),
+            mip_levels: OnceLock::new(),
+        }
+    }
+
     pub fn width(&self) -> u32 {
         self.width
     }
@@ -92,7 +104,51 @@ impl RtwImage {
             MAGENTA
         }
     }
+
+    fn mip_levels(&self) -> &[MipLevel] {
+        // Halves the image until it is a single texel. Textures only pay for this once they
+        // are seen from far enough for their texels to be smaller than a pixel.
+        self.mip_levels.get_or_init(|| {
+            let mut levels: Vec<MipLevel> = Vec::new();
+            let Some(bdata) = &self.bdata else {
+                return levels;
+            };
+            let (mut width, mut height, mut data) = (self.width, self.height, bdata.as_slice());
+            while width > 1 || height > 1 {
+                levels.push(MipLevel::downsample(width, height, data));
+                let last = levels.last().unwrap();
+                (width, height, data) = (last.width, last.height, last.bdata.as_slice());
+            }
+            levels
+        })
+    }
+
+    pub fn level_count(&self) -> usize {
+        // Number of mip map levels, counting the image itself as level 0.
+        if self.no_data() {
+            return 1;
+        }
+        1 + self.mip_levels().len()
+    }
+
+    pub fn level_size(&self, level: usize) -> (u32, u32) {
+        if level == 0 {
+            return (self.width, self.height);
+        }
+        let mip = &self.mip_levels()[level - 1];
+        (mip.width, mip.height)
+    }
+
+    pub fn level_pixel_data(&self, level: usize, x: u32, y: u32) -> &[u8] {
+        // Like pixel_data, for the texel at x,y of the given mip map level.
+        if level == 0 {
+            return self.pixel_data(x, y);
+        }
+        let mip = &self.mip_levels()[level - 1];
+        let index = ((y.min(mip.height - 1) * mip.width + x.min(mip.width - 1)) * 3) as usize;
+        &mip.bdata[index..index + 3]
+    }
 }
 
 // Usage:
-// let image = RtwImage::new("example.png").expect("Image failed to load");
+// let image = RtwImage::new(Path::new("example.png"))?;
@@ -0,0 +1,411 @@
+use std::str::FromStr;
+
+/// Source of the sample values used to build a path.
+///
+/// Before each camera sample the renderer calls `start_pixel_sample`, then every decision along
+/// the path (film position, lens position, time, scattering directions, ...) consumes the next
+/// one or two dimensions of the sample vector. Low-discrepancy samplers spread the values of
+/// each dimension evenly over the samples of a pixel, which reduces noise at equal sample count.
+pub trait Sampler: Send {
+    fn start_pixel_sample(&mut self, i: usize, j: usize, index: u32);
+
+    fn get_1d(&mut self) -> f64;
+
+    fn get_2d(&mut self) -> (f64, f64);
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub enum SamplerKind {
+    #[default]
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+    BlueNoise,
+}
+
+impl SamplerKind {
+    pub fn create(&self, samples_per_pixel: u32, seed: u64) -> Box<dyn Sampler> {
+        // Samplers made with the same seed give the same values for a pixel, so that the bands
+        // of an image, each with its own sampler, draw from one sample pattern.
+        match self {
+            Self::Independent => Box::new(IndependentSampler),
+            Self::Stratified => Box::new(StratifiedSampler::new(samples_per_pixel, seed)),
+            Self::Halton => Box::new(HaltonSampler::new(seed)),
+            Self::Sobol => Box::new(SobolSampler::new(samples_per_pixel, seed)),
+            Self::BlueNoise => Box::new(BlueNoiseSampler::new(samples_per_pixel, seed)),
+        }
+    }
+}
+
+impl FromStr for SamplerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "independent" => Ok(Self::Independent),
+            "stratified" => Ok(Self::Stratified),
+            "halton" => Ok(Self::Halton),
+            "sobol" => Ok(Self::Sobol),
+            "blue-noise" => Ok(Self::BlueNoise),
+            _ => Err(format!("unknown sampler '{s}'")),
+        }
+    }
+}
+
+// Largest f64 below one, sample values are kept in [0, 1).
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+
+fn mix_bits(mut v: u64) -> u64 {
+    // Finalizer of MurmurHash3, spreads every input bit over the whole output.
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5d329728ea185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81dadef4bc2dd44d);
+    v ^= v >> 33;
+    v
+}
+
+fn hash(seed: u64, i: usize, j: usize, dim: u32) -> u64 {
+    mix_bits(seed ^ mix_bits(((i as u64) << 40) ^ ((j as u64) << 16) ^ dim as u64))
+}
+
+fn hash_to_float(h: u64) -> f64 {
+    (h >> 11) as f64 * (1.0 / (1u64 << 53) as f64)
+}
+
+fn permutation_element(mut i: u32, n: u32, seed: u32) -> u32 {
+    // Returns the i-th element of a pseudo-random permutation of [0, n) selected by seed, without
+    // building the permutation (Kensler, "Correlated Multi-Jittered Sampling").
+    let mut w = n.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < n {
+            break;
+        }
+    }
+    i.wrapping_add(seed) % n
+}
+
+/// Uniform random values, every sample and dimension is independent.
+pub struct IndependentSampler;
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, _i: usize, _j: usize, _index: u32) {}
+
+    fn get_1d(&mut self) -> f64 {
+        rand::random_range(0.0..1.0)
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (rand::random_range(0.0..1.0), rand::random_range(0.0..1.0))
+    }
+}
+
+/// Jittered stratification of every dimension, with the strata of each dimension visited in a
+/// different random order so that dimensions stay uncorrelated.
+pub struct StratifiedSampler {
+    samples_per_pixel: u32,
+    x_strata: u32,
+    y_strata: u32,
+    seed: u64,
+    pixel: (usize, usize),
+    index: u32,
+    dim: u32,
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: u32, seed: u64) -> Self {
+        // Split the samples in a grid as square as possible, 2D strata need x * y samples.
+        let samples_per_pixel = samples_per_pixel.max(1);
+        let mut x_strata = (samples_per_pixel as f64).sqrt() as u32;
+        while !samples_per_pixel.is_multiple_of(x_strata) {
+            x_strata -= 1;
+        }
+        Self {
+            samples_per_pixel,
+            x_strata,
+            y_strata: samples_per_pixel / x_strata,
+            seed,
+            pixel: (0, 0),
+            index: 0,
+            dim: 0,
+        }
+    }
+
+    fn next_hash(&mut self) -> u64 {
+        let h = hash(self.seed, self.pixel.0, self.pixel.1, self.dim);
+        self.dim += 1;
+        h
+    }
+
+    fn stratum(&self, h: u64) -> u32 {
+        permutation_element(
+            self.index % self.samples_per_pixel,
+            self.samples_per_pixel,
+            h as u32,
+        )
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, i: usize, j: usize, index: u32) {
+        self.pixel = (i, j);
+        self.index = index;
+        self.dim = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let h = self.next_hash();
+        let stratum = self.stratum(h);
+        let jitter = hash_to_float(mix_bits(h ^ self.index as u64));
+        ((stratum as f64 + jitter) / self.samples_per_pixel as f64).min(ONE_MINUS_EPSILON)
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let h = self.next_hash();
+        self.dim += 1;
+        let stratum = self.stratum(h);
+        let (x, y) = (stratum % self.x_strata, stratum / self.x_strata);
+        let jitter_x = hash_to_float(mix_bits(h ^ self.index as u64));
+        let jitter_y = hash_to_float(mix_bits(h.rotate_left(32) ^ self.index as u64));
+        (
+            ((x as f64 + jitter_x) / self.x_strata as f64).min(ONE_MINUS_EPSILON),
+            ((y as f64 + jitter_y) / self.y_strata as f64).min(ONE_MINUS_EPSILON),
+        )
+    }
+}
+
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+/// Halton sequence, dimension d uses the radical inverse in the d-th prime base. Each pixel gets
+/// its own random toroidal shift of the sequence, and dimensions past the prime table fall back
+/// to hashed random values.
+pub struct HaltonSampler {
+    seed: u64,
+    pixel: (usize, usize),
+    index: u32,
+    dim: u32,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            pixel: (0, 0),
+            index: 0,
+            dim: 0,
+        }
+    }
+
+    fn radical_inverse(base: u32, mut a: u32) -> f64 {
+        let inv_base = 1.0 / base as f64;
+        let mut inv_base_m = 1.0;
+        let mut reversed = 0u64;
+        while a > 0 {
+            let next = a / base;
+            let digit = a - next * base;
+            reversed = reversed * base as u64 + digit as u64;
+            inv_base_m *= inv_base;
+            a = next;
+        }
+        (reversed as f64 * inv_base_m).min(ONE_MINUS_EPSILON)
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, i: usize, j: usize, index: u32) {
+        self.pixel = (i, j);
+        self.index = index;
+        self.dim = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let dim = self.dim;
+        self.dim += 1;
+
+        let h = hash(self.seed, self.pixel.0, self.pixel.1, dim);
+        match PRIMES.get(dim as usize) {
+            Some(&base) => {
+                let shift = hash_to_float(h);
+                (Self::radical_inverse(base, self.index) + shift).fract()
+            }
+            None => hash_to_float(mix_bits(h ^ self.index as u64)),
+        }
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+fn sobol_sample(index: u32, dim: u32) -> u32 {
+    // The first two dimensions of the Sobol sequence, as 32-bit fixed point fractions.
+    if dim == 0 {
+        index.reverse_bits()
+    } else {
+        let mut i = index;
+        let mut r = 0;
+        let mut v = 1u32 << 31;
+        while i != 0 {
+            if i & 1 != 0 {
+                r ^= v;
+            }
+            i >>= 1;
+            v ^= v >> 1;
+        }
+        r
+    }
+}
+
+fn owen_scramble(v: u32, seed: u32) -> u32 {
+    // Nested uniform scrambling through the Laine-Karras hash, which only lets each bit depend
+    // on the bits above it.
+    let mut x = v.reverse_bits();
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x.reverse_bits()
+}
+
+fn to_unit_float(v: u32) -> f64 {
+    (v as f64 / (1u64 << 32) as f64).min(ONE_MINUS_EPSILON)
+}
+
+/// Padded Owen-scrambled Sobol points: every pair of dimensions is a (0, 2)-sequence in its own
+/// shuffled order, so consecutive 2D draws are all well distributed and never correlated.
+pub struct SobolSampler {
+    samples_per_pixel: u32,
+    seed: u64,
+    pixel: (usize, usize),
+    index: u32,
+    dim: u32,
+}
+
+impl SobolSampler {
+    pub fn new(samples_per_pixel: u32, seed: u64) -> Self {
+        Self {
+            samples_per_pixel: samples_per_pixel.max(1),
+            seed,
+            pixel: (0, 0),
+            index: 0,
+            dim: 0,
+        }
+    }
+
+    fn next_hash(&mut self) -> u64 {
+        let h = hash(self.seed, self.pixel.0, self.pixel.1, self.dim);
+        self.dim += 1;
+        h
+    }
+
+    fn shuffled_index(&self, h: u64) -> u32 {
+        permutation_element(
+            self.index % self.samples_per_pixel,
+            self.samples_per_pixel,
+            h as u32,
+        ) + self.index / self.samples_per_pixel * self.samples_per_pixel
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, i: usize, j: usize, index: u32) {
+        self.pixel = (i, j);
+        self.index = index;
+        self.dim = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let h = self.next_hash();
+        let index = self.shuffled_index(h);
+        to_unit_float(owen_scramble(sobol_sample(index, 0), (h >> 32) as u32))
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let h = self.next_hash();
+        self.dim += 1;
+        let index = self.shuffled_index(h);
+        let h2 = mix_bits(h);
+        (
+            to_unit_float(owen_scramble(sobol_sample(index, 0), (h2 >> 32) as u32)),
+            to_unit_float(owen_scramble(sobol_sample(index, 1), h2 as u32)),
+        )
+    }
+}
+
+/// Sobol points shared by every pixel and shifted per pixel by a blue-noise dither mask, so the
+/// remaining error is pushed to high screen-space frequencies where it is least visible.
+///
+/// The mask is the R2 low-discrepancy dither, which has a blue-noise-like spectrum without
+/// requiring a precomputed texture; each dimension reads it with a different golden-ratio offset.
+pub struct BlueNoiseSampler {
+    sobol: SobolSampler,
+    pixel: (usize, usize),
+    dim: u32,
+}
+
+impl BlueNoiseSampler {
+    pub fn new(samples_per_pixel: u32, seed: u64) -> Self {
+        Self {
+            sobol: SobolSampler::new(samples_per_pixel, seed),
+            pixel: (0, 0),
+            dim: 0,
+        }
+    }
+
+    fn dither(&mut self) -> f64 {
+        const A1: f64 = 0.754_877_666_246_692_7; // 1 / plastic number
+        const A2: f64 = 0.569_840_290_998_053_2; // 1 / plastic number^2
+        const GOLDEN: f64 = 0.618_033_988_749_894_9;
+
+        let (i, j) = (self.pixel.0 as f64, self.pixel.1 as f64);
+        let d = (A1 * i + A2 * j + GOLDEN * self.dim as f64).fract();
+        self.dim += 1;
+        d
+    }
+}
+
+impl Sampler for BlueNoiseSampler {
+    fn start_pixel_sample(&mut self, i: usize, j: usize, index: u32) {
+        // Every pixel reads the same sequence, only the dither decorrelates them.
+        self.sobol.start_pixel_sample(0, 0, index);
+        self.pixel = (i, j);
+        self.dim = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        (self.sobol.get_1d() + self.dither()).fract()
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let (u, v) = self.sobol.get_2d();
+        ((u + self.dither()).fract(), (v + self.dither()).fract())
+    }
+}
@@ -1,8 +1,9 @@
 use crate::aabb::AABB;
-use crate::hittable::{HitRecord, Hittable};
+use crate::hittable::{Hit, HitRecord, Hittable, SurfacePoint};
 use crate::interval::Interval;
 use crate::material::Material;
 use crate::ray::Ray;
+use crate::rtweekend::gamma;
 use crate::vec3::{Point, Vec3f64};
 use std::sync::Arc;
 
@@ -57,10 +58,26 @@ impl Sphere {
 
         (phi / (2.0 * PI), theta / PI)
     }
-}
 
-impl Hittable for Sphere {
-    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+    fn get_sphere_dpduv(&self, n: &Vec3f64) -> (Vec3f64, Vec3f64) {
+        // Derivatives of the point with the coordinates of get_sphere_uv, at the unit normal n:
+        // u turns around the Y axis and v runs along a meridian. At the poles, where the
+        // meridians meet, dpdv is left undefined (zero).
+        use std::f64::consts::PI;
+
+        let (x, y, z) = (*n.x(), *n.y(), *n.z());
+        let dpdu = Vec3f64::new(z, 0.0, -x) * (2.0 * PI * self.radius);
+        let s = (x * x + z * z).sqrt();
+        let dpdv = if s > 0.0 {
+            Vec3f64::new(-x * y / s, s, -y * z / s) * (PI * self.radius)
+        } else {
+            Vec3f64::zero()
+        };
+        (dpdu, dpdv)
+    }
+
+    fn intersect(&self, r: &Ray, ray_t: Interval) -> Option<f64> {
+        // Returns the nearest root within ray_t.
         let current_center = self.center.at(r.time());
         let oc = &current_center - r.origin();
         let a = r.direction().length_squared();
@@ -83,18 +100,45 @@ impl Hittable for Sphere {
             }
         }
 
-        let t = root;
-        let p = r.at(t);
-        let outward_normal = (&p - current_center) / self.radius;
-        let uv = Self::get_sphere_uv(&outward_normal);
-        Some(HitRecord::new(
-            r,
+        Some(root)
+    }
+}
+
+impl Hittable for Sphere {
+    fn closest_hit(&self, r: &Ray, ray_t: Interval) -> Option<Hit> {
+        self.intersect(r, ray_t).map(Hit::new)
+    }
+
+    fn interaction(&self, r: &Ray, hit: &mut Hit) -> HitRecord {
+        let point = self.surface_point(r, hit);
+        let uv = Self::get_sphere_uv(&point.normal);
+        let (dpdu, dpdv) = self.get_sphere_dpduv(&point.normal);
+        HitRecord::new(r, point.t, point.p, point.normal, self.mat.clone(), uv)
+            .with_p_error(point.p_error)
+            .with_dpduv(dpdu, dpdv)
+    }
+
+    fn surface_point(&self, r: &Ray, hit: &mut Hit) -> SurfacePoint {
+        let t = hit.t;
+        let current_center = self.center.at(r.time());
+
+        // The point found along the ray is projected back onto the sphere, which bounds its
+        // error by a few ulps of its distance to the center rather than by the error of t.
+        let offset = r.at(t) - &current_center;
+        let normal = offset.unit_vector();
+        let offset = &normal * self.radius;
+        let p = &current_center + &offset;
+        let p_error = offset.abs() * gamma(5) + p.abs() * gamma(1);
+        SurfacePoint {
             t,
             p,
-            outward_normal,
-            self.mat.clone(),
-            uv,
-        ))
+            p_error,
+            normal,
+        }
+    }
+
+    fn occluded(&self, r: &Ray, ray_t: Interval) -> bool {
+        self.intersect(r, ray_t).is_some()
     }
 
     fn bounding_box(&self) -> &AABB {
@@ -123,42 +167,56 @@ impl Magnifier {
         bbox = bbox.rotate::<1>(yaw);
         let pitch = d.y().atan2((d.x().powi(2) + d.z().powi(2)).sqrt());
         bbox = bbox.rotate::<0>(pitch);
-        bbox = &bbox + &p;  // not efficient enough
+        bbox = &bbox + &p; // not efficient enough
 
         Self { sph0, sph1, bbox }
     }
 }
 
 impl Hittable for Magnifier {
-    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+    fn closest_hit(&self, r: &Ray, ray_t: Interval) -> Option<Hit> {
+        // The lens is the part common to both spheres. The ray enters it where it enters the
+        // second sphere, and leaves it where it leaves the first one.
+        let spheres = [&self.sph0, &self.sph1];
         let t_all = Interval::UNIVERSE;
-        let rec0 = self.sph0.hit(r, t_all)?;
-        let rec1 = self.sph1.hit(r, t_all)?;
-        if rec0.t < rec1.t {
-            let t_next = Interval::from(rec0.t + 0.0001, f64::INFINITY);
-            let rec2 = self.sph0.hit(r, t_next)?;
-            if rec2.t < rec1.t {
-                None
-            } else if ray_t.contains(rec1.t) {
-                Some(rec1)
-            } else if ray_t.contains(rec2.t) {
-                Some(rec2)
-            } else {
-                None
-            }
+        let mut hits = [
+            spheres[0].closest_hit(r, t_all)?,
+            spheres[1].closest_hit(r, t_all)?,
+        ];
+        let first = if hits[0].t < hits[1].t { 0 } else { 1 };
+        let second = 1 - first;
+
+        let entry = spheres[first].surface_point(r, &mut hits[first]);
+        let exit = entry.hit_beyond(spheres[first], r)?;
+        let (mut hit, index) = if exit.t < hits[second].t {
+            return None;
+        } else if ray_t.contains(hits[second].t) {
+            (hits[second].clone(), second)
+        } else if ray_t.contains(exit.t) {
+            (exit, first)
         } else {
-            let t_next = Interval::from(rec1.t + 0.0001, f64::INFINITY);
-            let rec2 = self.sph1.hit(r, t_next)?;
-            if rec2.t < rec0.t {
-                None
-            } else if ray_t.contains(rec0.t) {
-                Some(rec0)
-            } else if ray_t.contains(rec2.t) {
-                Some(rec2)
-            } else {
-                None
-            }
-        }
+            return None;
+        };
+        hit.push(index);
+        Some(hit)
+    }
+
+    fn interaction(&self, r: &Ray, hit: &mut Hit) -> HitRecord {
+        let sphere = if hit.pop() == 0 {
+            &self.sph0
+        } else {
+            &self.sph1
+        };
+        sphere.interaction(r, hit)
+    }
+
+    fn surface_point(&self, r: &Ray, hit: &mut Hit) -> SurfacePoint {
+        let sphere = if hit.pop() == 0 {
+            &self.sph0
+        } else {
+            &self.sph1
+        };
+        sphere.surface_point(r, hit)
     }
 
     fn bounding_box(&self) -> &AABB {
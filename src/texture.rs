@@ -1,12 +1,41 @@
+use crate::asset::{AssetError, AssetResolver};
 use crate::color::Color;
 use crate::interval::Interval;
 use crate::perlin::Perlin;
 use crate::rtwimage::RtwImage;
-use crate::vec3::Point;
+use crate::vec3::{Point, Vec3f64};
 use std::sync::Arc;
 
+/// Change of the texture coordinates from one pixel to the next, along the image x and y axes.
+///
+/// Zero when unknown, e.g. past a diffuse bounce, which makes filtered lookups point samples.
+#[derive(Clone, Default)]
+pub struct UVDerivatives {
+    pub dudx: f64,
+    pub dvdx: f64,
+    pub dudy: f64,
+    pub dvdy: f64,
+}
+
+impl UVDerivatives {
+    fn scaled(&self, su: f64, sv: f64) -> Self {
+        Self {
+            dudx: self.dudx * su,
+            dvdx: self.dvdx * sv,
+            dudy: self.dudy * su,
+            dvdy: self.dvdy * sv,
+        }
+    }
+}
+
 pub trait Texture: Send + Sync {
     fn value(&self, u: f64, v: f64, p: &Point) -> Color;
+
+    // Value averaged over the footprint of a pixel around u,v. Textures without detail finer
+    // than a pixel can keep the point sample.
+    fn filtered(&self, u: f64, v: f64, p: &Point, _duv: &UVDerivatives) -> Color {
+        self.value(u, v, p)
+    }
 }
 
 pub struct SolidColor {
@@ -49,30 +78,88 @@ impl CheckerTexture {
     }
 }
 
-impl Texture for CheckerTexture {
-    fn value(&self, u: f64, v: f64, p: &Point) -> Color {
+impl CheckerTexture {
+    fn select(&self, p: &Point) -> &dyn Texture {
         let x = (self.inv_scale * p.x()).floor() as i32;
         let y = (self.inv_scale * p.y()).floor() as i32;
         let z = (self.inv_scale * p.z()).floor() as i32;
 
         if (x + y + z) % 2 == 0 {
-            self.even.value(u, v, p)
+            self.even.as_ref()
         } else {
-            self.odd.value(u, v, p)
+            self.odd.as_ref()
         }
     }
 }
 
+impl Texture for CheckerTexture {
+    fn value(&self, u: f64, v: f64, p: &Point) -> Color {
+        self.select(p).value(u, v, p)
+    }
+
+    fn filtered(&self, u: f64, v: f64, p: &Point, duv: &UVDerivatives) -> Color {
+        self.select(p).filtered(u, v, p, duv)
+    }
+}
+
 pub struct ImageTexture {
-    image: RtwImage,
+    image: Arc<RtwImage>, // Shared with other textures using the same file
+    linear: bool,         // Texel values are used as is instead of being decoded from gamma 2
 }
 
 impl ImageTexture {
-    pub fn new(filename: &str) -> Self {
+    pub fn new(filename: &str, assets: &AssetResolver) -> Result<Self, AssetError> {
+        Ok(Self::from_image(assets.image(filename, None)?))
+    }
+
+    pub fn from_image(image: Arc<RtwImage>) -> Self {
         Self {
-            image: RtwImage::new(filename),
+            image,
+            linear: false,
         }
     }
+
+    pub fn from_linear_image(image: Arc<RtwImage>) -> Self {
+        // For images holding data rather than colors, like roughness or metalness.
+        Self {
+            image,
+            linear: true,
+        }
+    }
+}
+
+impl ImageTexture {
+    fn texel(&self, level: usize, x: u32, y: u32) -> Color {
+        let pixel = self.image.level_pixel_data(level, x, y);
+        let color_scale = 1.0 / 255.0;
+        Color::new(
+            color_scale * pixel[0] as f64,
+            color_scale * pixel[1] as f64,
+            color_scale * pixel[2] as f64,
+        )
+    }
+
+    fn bilinear(&self, level: usize, u: f64, v: f64) -> Color {
+        // Blends the four texels of the level nearest to u,v, which are in image coordinates.
+        let (width, height) = self.image.level_size(level);
+        let x = u * width as f64 - 0.5;
+        let y = v * height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+
+        let clamp = |i: f64, size: u32| i.clamp(0.0, (size - 1) as f64) as u32;
+        let (xa, xb) = (clamp(x0, width), clamp(x0 + 1.0, width));
+        let (ya, yb) = (clamp(y0, height), clamp(y0 + 1.0, height));
+
+        self.texel(level, xa, ya) * ((1.0 - fx) * (1.0 - fy))
+            + self.texel(level, xb, ya) * (fx * (1.0 - fy))
+            + self.texel(level, xa, yb) * ((1.0 - fx) * fy)
+            + self.texel(level, xb, yb) * (fx * fy)
+    }
+
+    fn decode(&self, color: Color) -> Color {
+        if self.linear { color } else { &color * &color }
+    }
 }
 
 impl Texture for ImageTexture {
@@ -86,17 +173,93 @@ impl Texture for ImageTexture {
         u = Interval::I01.clamp(u);
         v = 1.0 - Interval::I01.clamp(v); // Flip V to image coordinates
 
+        let color = self.texel(
+            0,
+            (u * self.image.width() as f64) as u32,
+            (v * self.image.height() as f64) as u32,
+        );
+        self.decode(color)
+    }
+
+    fn filtered(&self, u: f64, v: f64, p: &Point, duv: &UVDerivatives) -> Color {
+        // Trilinear filtering: the width of the footprint in texels falls between two levels of
+        // the mip map, whose bilinear lookups are blended. Footprints within a texel keep the
+        // nearest texel of value, so magnified textures look the same as before.
+        let (width, height) = (self.image.width() as f64, self.image.height() as f64);
+        let footprint = f64::max(
+            (duv.dudx * width).hypot(duv.dvdx * height),
+            (duv.dudy * width).hypot(duv.dvdy * height),
+        );
+        if self.image.no_data() || footprint <= 1.0 {
+            return self.value(u, v, p);
+        }
+
+        let u = Interval::I01.clamp(u);
+        let v = 1.0 - Interval::I01.clamp(v);
+
+        let last = self.image.level_count() - 1;
+        let level = footprint.log2().min(last as f64);
+        let lower = level.floor() as usize;
+        let blend = level - lower as f64;
+        let mut color = self.bilinear(lower, u, v);
+        if lower < last && blend > 0.0 {
+            color = color * (1.0 - blend) + self.bilinear(lower + 1, u, v) * blend;
+        }
+        self.decode(color)
+    }
+}
+
+/// Tangent-space normal map.
+///
+/// Each texel stores a unit normal with its components remapped from [-1, 1] to [0, 255], the
+/// blue channel being the component along the unperturbed surface normal.
+pub struct NormalMap {
+    image: Arc<RtwImage>,
+}
+
+impl NormalMap {
+    pub fn from_image(image: Arc<RtwImage>) -> Self {
+        Self { image }
+    }
+
+    pub fn normal(&self, u: f64, v: f64) -> Vec3f64 {
+        // Without image data, leave the surface normal unperturbed.
+        if self.image.no_data() {
+            return Vec3f64::new(0.0, 0.0, 1.0);
+        }
+
+        let u = Interval::I01.clamp(u);
+        let v = 1.0 - Interval::I01.clamp(v);
+
         let pixel = self.image.pixel_data(
             (u * self.image.width() as f64) as u32,
             (v * self.image.height() as f64) as u32,
         );
 
-        let color_scale = 1.0 / 255.0;
-        Color::new(
-            (color_scale * pixel[0] as f64).powi(2),
-            (color_scale * pixel[1] as f64).powi(2),
-            (color_scale * pixel[2] as f64).powi(2),
-        )
+        // The texel values are stored linearly, unlike the colors of an ImageTexture.
+        let decode = |c: u8| 2.0 * c as f64 / 255.0 - 1.0;
+        Vec3f64::new(decode(pixel[0]), decode(pixel[1]), decode(pixel[2])).into_unit_vector()
+    }
+}
+
+pub struct ScaledTexture {
+    tex: Arc<dyn Texture>,
+    scale: Color,
+}
+
+impl ScaledTexture {
+    pub fn new(tex: Arc<dyn Texture>, scale: Color) -> Self {
+        Self { tex, scale }
+    }
+}
+
+impl Texture for ScaledTexture {
+    fn value(&self, u: f64, v: f64, p: &Point) -> Color {
+        self.tex.value(u, v, p) * &self.scale
+    }
+
+    fn filtered(&self, u: f64, v: f64, p: &Point, duv: &UVDerivatives) -> Color {
+        self.tex.filtered(u, v, p, duv) * &self.scale
     }
 }
 
@@ -155,34 +318,19 @@ impl Texture for StackedPaddedTexture {
             self.back.value(u, v, p)
         }
     }
-}
-
-type UV = (f64, f64);
 
-pub struct SubTexture {
-    base: Arc<dyn Texture>,
-    uv: UV,
-    dir_u: UV,
-    dir_v: UV,
-}
-
-impl SubTexture {
-    pub fn new(base: Arc<dyn Texture>, uv0: UV, uv1: UV, uv2: UV) -> Self {
-        Self {
-            base,
-            uv: uv0,
-            dir_u: (uv1.0 - uv0.0, uv1.1 - uv0.1),
-            dir_v: (uv2.0 - uv0.0, uv2.1 - uv0.1),
+    fn filtered(&self, u: f64, v: f64, p: &Point, duv: &UVDerivatives) -> Color {
+        // The front texture is stretched over the padded interval, so its footprint is
+        // scaled the same way.
+        if self.i_u.contains(u) && self.i_v.contains(v) {
+            self.front.filtered(
+                (u - self.i_u.min) / self.i_u.size(),
+                (v - self.i_v.min) / self.i_v.size(),
+                p,
+                &duv.scaled(1.0 / self.i_u.size(), 1.0 / self.i_v.size()),
+            )
+        } else {
+            self.back.filtered(u, v, p, duv)
         }
     }
 }
-
-impl Texture for SubTexture {
-    fn value(&self, u: f64, v: f64, p: &Point) -> Color {
-        self.base.value(
-            self.uv.0 + u * self.dir_u.0 + v * self.dir_v.0,
-            self.uv.1 + u * self.dir_u.1 + v * self.dir_v.1,
-            p,
-        )
-    }
-}
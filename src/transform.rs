@@ -0,0 +1,180 @@
+use crate::aabb::AABB;
+use crate::rtweekend::{degrees_to_radians, gamma};
+use crate::vec3::{Point, Vec3f64};
+
+type Matrix = [[f64; 4]; 3]; // Affine 3x4 matrix, the last row is implicitly (0, 0, 0, 1)
+
+/// Affine transformation from object space to world space.
+///
+/// The inverse is kept alongside the matrix, since rays are transformed into object space while
+/// hit points and normals are transformed back to world space.
+#[derive(Clone, Debug)]
+pub struct Transform {
+    m: Matrix,
+    inv: Matrix,
+}
+
+impl Transform {
+    const IDENTITY: Matrix = [
+        [1.0, 0.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+    ];
+
+    pub fn identity() -> Self {
+        Self {
+            m: Self::IDENTITY,
+            inv: Self::IDENTITY,
+        }
+    }
+
+    pub fn from_matrix(m: [[f64; 4]; 3]) -> Self {
+        // Builds the transform from the top three rows of an affine 4x4 matrix. The inverse of
+        // the linear part is its adjugate over its determinant, and the inverse translation
+        // undoes the original one.
+        let a = |i: usize, j: usize| m[i % 3][j % 3];
+        let det = a(0, 0) * (a(1, 1) * a(2, 2) - a(1, 2) * a(2, 1))
+            - a(0, 1) * (a(1, 0) * a(2, 2) - a(1, 2) * a(2, 0))
+            + a(0, 2) * (a(1, 0) * a(2, 1) - a(1, 1) * a(2, 0));
+
+        let mut inv = [[0.0; 4]; 3];
+        for (i, row) in inv.iter_mut().enumerate() {
+            for (j, x) in row.iter_mut().take(3).enumerate() {
+                *x = (a(j + 1, i + 1) * a(j + 2, i + 2) - a(j + 1, i + 2) * a(j + 2, i + 1)) / det;
+            }
+            row[3] = -(0..3).map(|k| row[k] * m[k][3]).sum::<f64>();
+        }
+
+        Self { m, inv }
+    }
+
+    pub fn translate(offset: &Vec3f64) -> Self {
+        let mut m = Self::IDENTITY;
+        let mut inv = Self::IDENTITY;
+        for i in 0..3 {
+            m[i][3] = offset[i];
+            inv[i][3] = -offset[i];
+        }
+        Self { m, inv }
+    }
+
+    pub fn scale(factors: &Vec3f64) -> Self {
+        let mut m = Self::IDENTITY;
+        let mut inv = Self::IDENTITY;
+        for i in 0..3 {
+            m[i][i] = factors[i];
+            inv[i][i] = 1.0 / factors[i];
+        }
+        Self { m, inv }
+    }
+
+    pub fn rotate<const AXIS: usize>(angle: f64) -> Self {
+        // Counter-clockwise rotation by angle (in degrees) around the given axis, following the
+        // same convention as AABB::rotate. The inverse of a rotation is its transpose.
+        let (sin_theta, cos_theta) = degrees_to_radians(angle).sin_cos();
+        let (a, b) = ((AXIS + 1) % 3, (AXIS + 2) % 3);
+
+        let mut m = Self::IDENTITY;
+        m[a][a] = cos_theta;
+        m[a][b] = -sin_theta;
+        m[b][a] = sin_theta;
+        m[b][b] = cos_theta;
+
+        let mut inv = m;
+        inv[a][b] = sin_theta;
+        inv[b][a] = -sin_theta;
+
+        Self { m, inv }
+    }
+
+    pub fn then(&self, next: &Self) -> Self {
+        // Returns the transform applying self first and then next.
+        Self {
+            m: Self::multiply(&next.m, &self.m),
+            inv: Self::multiply(&self.inv, &next.inv),
+        }
+    }
+
+    fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+        let mut r = [[0.0; 4]; 3];
+        for (i, row) in r.iter_mut().enumerate() {
+            for (j, x) in row.iter_mut().enumerate() {
+                *x = (0..3).map(|k| a[i][k] * b[k][j]).sum::<f64>();
+            }
+            row[3] += a[i][3];
+        }
+        r
+    }
+
+    pub fn point(&self, p: &Point) -> Point {
+        Self::apply(&self.m, p) + Vec3f64::new(self.m[0][3], self.m[1][3], self.m[2][3])
+    }
+
+    pub fn point_error(&self, p: &Point, p_error: &Vec3f64) -> Vec3f64 {
+        // Error bound of point(p), given the error of p: the matrix carries the error of p over
+        // and adds the rounding of its three products and three sums.
+        let m = &self.m;
+        let bound = |v: &Vec3f64| {
+            Vec3f64::new(
+                m[0][0].abs() * v[0] + m[0][1].abs() * v[1] + m[0][2].abs() * v[2],
+                m[1][0].abs() * v[0] + m[1][1].abs() * v[1] + m[1][2].abs() * v[2],
+                m[2][0].abs() * v[0] + m[2][1].abs() * v[1] + m[2][2].abs() * v[2],
+            )
+        };
+        let translation = Vec3f64::new(m[0][3], m[1][3], m[2][3]);
+        bound(p_error) * (1.0 + gamma(3)) + (bound(&p.abs()) + translation.abs()) * gamma(3)
+    }
+
+    pub fn vector(&self, v: &Vec3f64) -> Vec3f64 {
+        Self::apply(&self.m, v)
+    }
+
+    pub fn inverse_point(&self, p: &Point) -> Point {
+        Self::apply(&self.inv, p) + Vec3f64::new(self.inv[0][3], self.inv[1][3], self.inv[2][3])
+    }
+
+    pub fn inverse_vector(&self, v: &Vec3f64) -> Vec3f64 {
+        Self::apply(&self.inv, v)
+    }
+
+    fn apply(m: &Matrix, v: &Vec3f64) -> Vec3f64 {
+        // Applies the linear part of the matrix, ignoring the translation column.
+        Vec3f64::new(
+            m[0][0] * v[0] + m[0][1] * v[1] + m[0][2] * v[2],
+            m[1][0] * v[0] + m[1][1] * v[1] + m[1][2] * v[2],
+            m[2][0] * v[0] + m[2][1] * v[1] + m[2][2] * v[2],
+        )
+    }
+
+    pub fn normal(&self, n: &Vec3f64) -> Vec3f64 {
+        // Normals are transformed by the inverse transpose, so that they stay perpendicular to
+        // the surface under non-uniform scaling. The result is not normalized.
+        let inv = &self.inv;
+        Vec3f64::new(
+            inv[0][0] * n[0] + inv[1][0] * n[1] + inv[2][0] * n[2],
+            inv[0][1] * n[0] + inv[1][1] * n[1] + inv[2][1] * n[2],
+            inv[0][2] * n[0] + inv[1][2] * n[1] + inv[2][2] * n[2],
+        )
+    }
+
+    pub fn bbox(&self, bbox: &AABB) -> AABB {
+        // Bounds the transformed corners of the box.
+        let mut min = Point::all(f64::INFINITY);
+        let mut max = Point::all(-f64::INFINITY);
+
+        for i in 0..8 {
+            let corner = Point::new(
+                if i & 1 == 0 { bbox[0].min } else { bbox[0].max },
+                if i & 2 == 0 { bbox[1].min } else { bbox[1].max },
+                if i & 4 == 0 { bbox[2].min } else { bbox[2].max },
+            );
+            let p = self.point(&corner);
+            for c in 0..3 {
+                min[c] = min[c].min(p[c]);
+                max[c] = max[c].max(p[c]);
+            }
+        }
+
+        AABB::from_points(&min, &max)
+    }
+}
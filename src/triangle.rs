@@ -0,0 +1,170 @@
+use crate::aabb::AABB;
+use crate::hittable::{Hit, HitRecord, Hittable, SurfacePoint};
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::rtweekend::gamma;
+use crate::vec3::{Point, Vec3f64};
+use std::sync::Arc;
+
+pub type UV = (f64, f64);
+
+/// Single triangle with optional per-vertex normals and texture coordinates.
+///
+/// Meshes store their triangles as indices into shared vertex arrays instead, see
+/// `TriangleMesh`. This is for triangles placed on their own.
+pub struct Triangle {
+    p: [Point; 3],
+    n: Option<[Vec3f64; 3]>, // Per-vertex shading normals
+    uv: [UV; 3],             // Per-vertex texture coordinates
+    mat: Arc<dyn Material>,
+
+    normal: Vec3f64, // Unit geometric normal
+    bbox: AABB,
+}
+
+impl Triangle {
+    pub fn with_vertex_data(
+        p: [Point; 3],
+        n: Option<[Vec3f64; 3]>,
+        uv: Option<[UV; 3]>,
+        mat: Arc<dyn Material>,
+    ) -> Self {
+        // Without per-vertex normals the winding order decides the outward side. With them, the
+        // geometric normal is flipped to agree with their average, so both always face the same
+        // way whatever the winding of the source data.
+        let mut normal = (&p[1] - &p[0]).cross(&(&p[2] - &p[0])).into_unit_vector();
+        if let Some(n) = &n
+            && normal.dot(&(&n[0] + &n[1] + &n[2])) < 0.0
+        {
+            normal = -normal;
+        }
+
+        let bbox = AABB::from_aabbs(
+            &AABB::from_points(&p[0], &p[1]),
+            &AABB::from_points(&p[1], &p[2]),
+        );
+
+        Self {
+            p,
+            n,
+            uv: uv.unwrap_or([(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]),
+            mat,
+            normal,
+            bbox,
+        }
+    }
+}
+
+impl Hittable for Triangle {
+    fn closest_hit(&self, r: &Ray, ray_t: Interval) -> Option<Hit> {
+        let (t, b1, b2) = intersect(self.p.each_ref(), r, ray_t)?;
+        Some(Hit::with_coords(t, (b1, b2)))
+    }
+
+    fn interaction(&self, r: &Ray, hit: &mut Hit) -> HitRecord {
+        let (b1, b2) = hit.coords;
+        let b0 = 1.0 - b1 - b2;
+        let uv = (
+            b0 * self.uv[0].0 + b1 * self.uv[1].0 + b2 * self.uv[2].0,
+            b0 * self.uv[0].1 + b1 * self.uv[1].1 + b2 * self.uv[2].1,
+        );
+
+        let (p, p_error) = hit_point(self.p.each_ref(), b1, b2);
+        let mut rec = HitRecord::new(r, hit.t, p, self.normal.clone(), self.mat.clone(), uv)
+            .with_p_error(p_error);
+        if let Some((dpdu, dpdv)) = dpduv(self.p.each_ref(), self.uv) {
+            rec = rec.with_dpduv(dpdu, dpdv);
+        }
+
+        match &self.n {
+            Some(n) => rec.with_shading_normal(interpolate(n, b1, b2).into_unit_vector()),
+            None => rec,
+        }
+    }
+
+    fn surface_point(&self, _r: &Ray, hit: &mut Hit) -> SurfacePoint {
+        let (b1, b2) = hit.coords;
+        let (p, p_error) = hit_point(self.p.each_ref(), b1, b2);
+        SurfacePoint {
+            t: hit.t,
+            p,
+            p_error,
+            normal: self.normal.clone(),
+        }
+    }
+
+    fn occluded(&self, r: &Ray, ray_t: Interval) -> bool {
+        intersect(self.p.each_ref(), r, ray_t).is_some()
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        &self.bbox
+    }
+}
+
+pub fn intersect(p: [&Point; 3], r: &Ray, ray_t: Interval) -> Option<(f64, f64, f64)> {
+    // Möller-Trumbore ray/triangle intersection. Returns the ray parameter t and the barycentric
+    // coordinates (b1, b2) of the hit point, which is p0 + b1 (p1 - p0) + b2 (p2 - p0).
+
+    let edge1 = p[1] - p[0];
+    let edge2 = p[2] - p[0];
+    let pvec = r.direction().cross(&edge2);
+    let det = edge1.dot(&pvec);
+
+    // No hit if the ray is parallel to the triangle's plane.
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+
+    let tvec = r.origin() - p[0];
+    let b1 = tvec.dot(&pvec) * inv_det;
+    if !(0.0..=1.0).contains(&b1) {
+        return None;
+    }
+
+    let qvec = tvec.cross(&edge1);
+    let b2 = r.direction().dot(&qvec) * inv_det;
+    if b2 < 0.0 || b1 + b2 > 1.0 {
+        return None;
+    }
+
+    let t = edge2.dot(&qvec) * inv_det;
+    if !ray_t.surrounds(t) {
+        return None;
+    }
+
+    Some((t, b1, b2))
+}
+
+pub fn hit_point(p: [&Point; 3], b1: f64, b2: f64) -> (Point, Vec3f64) {
+    // Returns the point with barycentric coordinates (b1, b2) and a bound on its rounding
+    // error. Unlike the point along the ray, it is on the triangle up to a few ulps of the
+    // vertices.
+    let b0 = 1.0 - b1 - b2;
+    let (q0, q1, q2) = (p[0] * b0, p[1] * b1, p[2] * b2);
+    let error = (q0.abs() + q1.abs() + q2.abs()) * gamma(7);
+    (q0 + q1 + q2, error)
+}
+
+pub fn dpduv(p: [&Point; 3], uv: [UV; 3]) -> Option<(Vec3f64, Vec3f64)> {
+    // Returns the derivatives of the point on the triangle with the texture coordinates, or None
+    // if the texture coordinates of the triangle are degenerate.
+    let dp1 = p[1] - p[0];
+    let dp2 = p[2] - p[0];
+    let (du1, dv1) = (uv[1].0 - uv[0].0, uv[1].1 - uv[0].1);
+    let (du2, dv2) = (uv[2].0 - uv[0].0, uv[2].1 - uv[0].1);
+    let det = du1 * dv2 - dv1 * du2;
+    if det.abs() < 1e-12 {
+        return None;
+    }
+
+    let dpdu = (&dp1 * dv2 - &dp2 * dv1) / det;
+    let dpdv = (&dp2 * du1 - &dp1 * du2) / det;
+    Some((dpdu, dpdv))
+}
+
+pub fn interpolate(values: &[Vec3f64; 3], b1: f64, b2: f64) -> Vec3f64 {
+    &values[0] * (1.0 - b1 - b2) + &values[1] * b1 + &values[2] * b2
+}
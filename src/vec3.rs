@@ -8,6 +8,35 @@ pub struct Vec3<T>(pub(crate) [T; 3]);
 pub type Vec3f64 = Vec3<f64>;
 pub type Point = Vec3f64;
 
+/// Precision of stored geometry: the vertex attributes of meshes and the boxes of the four-wide
+/// BVH nodes, which are also tested against rays in this precision.
+///
+/// It is f32 with the `f32` feature, otherwise f64. This halves the memory of the vertices and of
+/// the wide BVHs, and doubles the boxes tested per SIMD operation. Hit points, shading and BVH
+/// builds are done in f64 either way, and binary BVHs, which are only traversed for comparison
+/// in the benchmark, keep f64 boxes.
+#[cfg(not(feature = "f32"))]
+pub type Real = f64;
+#[cfg(feature = "f32")]
+pub type Real = f32;
+
+#[allow(clippy::useless_conversion)] // Real may already be f64
+pub fn widen(x: Real) -> f64 {
+    f64::from(x)
+}
+
+pub fn round_down(x: f64) -> Real {
+    // The largest Real not above x, for lower bounds that must stay below x.
+    let y = x as Real;
+    if widen(y) > x { y.next_down() } else { y }
+}
+
+pub fn round_up(x: f64) -> Real {
+    // The smallest Real not below x, for upper bounds that must stay above x.
+    let y = x as Real;
+    if widen(y) < x { y.next_up() } else { y }
+}
+
 impl<T> Vec3<T> {
     pub fn new(x: T, y: T, z: T) -> Self {
         Self([x, y, z])
@@ -24,6 +53,18 @@ impl<T> Vec3<T> {
     }
 }
 
+impl Vec3f64 {
+    pub fn to_real(&self) -> Vec3<Real> {
+        Vec3(self.0.map(|x| x as Real))
+    }
+}
+
+impl Vec3<Real> {
+    pub fn to_f64(&self) -> Vec3f64 {
+        Vec3(self.0.map(widen))
+    }
+}
+
 impl<T> From<Vec3<T>> for [T; 3] {
     fn from(value: Vec3<T>) -> Self {
         value.0
@@ -63,6 +104,10 @@ impl Vec3f64 {
         self.dot(self)
     }
 
+    pub fn abs(&self) -> Self {
+        Self::new(self[0].abs(), self[1].abs(), self[2].abs())
+    }
+
     pub fn near_zero(&self) -> bool {
         // Return true if the vector is close to zero in all dimensions.
         let s = 1e-8;
@@ -123,6 +168,33 @@ impl Vec3f64 {
         }
     }
 
+    pub fn unit_vector_from_sample(u: (f64, f64)) -> Self {
+        // Maps a point of the unit square to a uniformly distributed direction.
+        let z = 1.0 - 2.0 * u.0;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let (sin_phi, cos_phi) = (2.0 * std::f64::consts::PI * u.1).sin_cos();
+        Self::new(r * cos_phi, r * sin_phi, z)
+    }
+
+    pub fn in_unit_disk_from_sample(u: (f64, f64)) -> Self {
+        // Maps a point of the unit square to the unit disk with Shirley and Chiu's concentric
+        // mapping, which keeps neighbouring samples close together.
+        use std::f64::consts::FRAC_PI_4;
+
+        let a = 2.0 * u.0 - 1.0;
+        let b = 2.0 * u.1 - 1.0;
+        if a == 0.0 && b == 0.0 {
+            return Self::zero();
+        }
+        let (r, theta) = if a.abs() > b.abs() {
+            (a, FRAC_PI_4 * (b / a))
+        } else {
+            (b, 2.0 * FRAC_PI_4 - FRAC_PI_4 * (a / b))
+        };
+        let (sin_theta, cos_theta) = theta.sin_cos();
+        Self::new(r * cos_theta, r * sin_theta, 0.0)
+    }
+
     pub fn reflect(&self, n: &Self) -> Self {
         let v = self;
         v - n * v.dot(n) * 2.0
@@ -463,9 +535,18 @@ where
 {
     type Output = Vec3<T>;
     fn div(mut self, rhs: T) -> Self::Output {
+        self /= rhs;
+        self
+    }
+}
+
+impl<T> DivAssign<T> for Vec3<T>
+where
+    T: DivAssign + Copy,
+{
+    fn div_assign(&mut self, rhs: T) {
         self.0[0] /= rhs;
         self.0[1] /= rhs;
         self.0[2] /= rhs;
-        self
     }
 }
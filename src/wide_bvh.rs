@@ -0,0 +1,503 @@
+use crate::aabb::AABB;
+use crate::bvh::LinearNode;
+use crate::cache::{CacheReader, CacheWriter};
+use crate::interval::Interval;
+use crate::ray::Ray;
+use crate::transform::Transform;
+use crate::vec3::{Real, round_down, round_up, widen};
+use std::io;
+
+// A node with the boxes of up to four children, stored lane by lane so that the four boxes are
+// tested against a ray at once. Unused lanes have empty boxes, which no ray hits, and child and
+// count 0, which no used lane has since the root is nobody's child. The boxes are stored as
+// Real, rounded outwards so that they still contain their child.
+#[derive(Clone)]
+#[repr(C, align(32))]
+struct WideNode {
+    bounds: [[Real; 4]; 6], // Min x, max x, min y, max y, min z and max z of each child
+    child: [u32; 4],        // Interior child: index of its node. Leaf: first primitive
+    count: [u32; 4],        // Number of primitives of a leaf child, 0 for interior children
+}
+
+impl WideNode {
+    const EMPTY: Self = Self {
+        bounds: [
+            [Real::INFINITY; 4],
+            [-Real::INFINITY; 4],
+            [Real::INFINITY; 4],
+            [-Real::INFINITY; 4],
+            [Real::INFINITY; 4],
+            [-Real::INFINITY; 4],
+        ],
+        child: [0; 4],
+        count: [0; 4],
+    };
+
+    fn is_used(&self, lane: usize) -> bool {
+        self.child[lane] != 0 || self.count[lane] != 0
+    }
+
+    fn lane_box(&self, lane: usize) -> AABB {
+        let axis = |a: usize| {
+            Interval::from(
+                widen(self.bounds[2 * a][lane]),
+                widen(self.bounds[2 * a + 1][lane]),
+            )
+        };
+        AABB::new(axis(0), axis(1), axis(2))
+    }
+
+    fn set_lane_box(&mut self, lane: usize, bbox: &AABB) {
+        for axis in 0..3 {
+            self.bounds[2 * axis][lane] = round_down(bbox[axis].min);
+            self.bounds[2 * axis + 1][lane] = round_up(bbox[axis].max);
+        }
+    }
+}
+
+// Ray data used by the box tests, along each axis, in the precision of the boxes. The origin is
+// rounded towards the box for the near planes and away from it for the far ones, so that the
+// distances computed err on the side of a hit.
+struct RayLanes {
+    origin: [[Real; 3]; 2], // Origin rounded for the near and for the far planes
+    inv_dir: [Real; 3],
+    neg: [usize; 3], // 1 if the ray goes towards negative coordinates, selecting the near plane
+    t_min: Real,     // Rounded down
+    t_max: Real,     // Rounded up
+}
+
+// Relative error bound of a slab distance computed in Real from the rounded origin: the
+// rounding of the inverse direction, the subtraction and the product, doubled for safety.
+const SLAB_ERROR: Real = {
+    let e = 3.0 * Real::EPSILON * 0.5;
+    2.0 * e / (1.0 - e)
+};
+
+// Child to visit, with the distance at which the ray enters its box.
+#[derive(Clone, Copy)]
+struct StackEntry {
+    child: u32,
+    count: u32,
+    t_near: f64,
+}
+
+/// Four-wide BVH, made by collapsing a binary one.
+///
+/// Every node holds up to four children, picked by opening the largest interior nodes of the
+/// binary tree first. Children are stored after their parent. The boxes of the four children are
+/// tested in the precision of Real, with one SIMD operation per step when the CPU supports AVX
+/// and with a scalar loop otherwise. With the `f32` feature, one operation covers both sides of
+/// the four boxes along an axis. The tree doesn't need the binary one once collapsed, it is
+/// refitted, transformed and cached on its own.
+pub struct WideBVH {
+    nodes: Vec<WideNode>,
+    bbox: AABB, // Bounds of all the primitives, in f64
+}
+
+impl WideBVH {
+    pub fn new(binary: &[LinearNode]) -> Self {
+        let mut bvh = Self {
+            nodes: Vec::with_capacity(binary.len() / 3 + 1),
+            bbox: binary[0].bbox.clone(),
+        };
+        if binary.len() == 1 && binary[0].count == 0 {
+            // An empty binary tree, whose root leaf holds nothing: no lane is ever hit.
+            bvh.nodes.push(WideNode::EMPTY);
+        } else {
+            bvh.collapse(binary, 0);
+        }
+        bvh
+    }
+
+    fn collapse(&mut self, binary: &[LinearNode], root: usize) -> u32 {
+        // Makes the wide node standing for the binary subtree at root, and returns its index.
+        // A root leaf becomes the only child of the node.
+        let children_of = |i: usize| [i + 1, binary[i].offset as usize];
+        let mut children = if binary[root].count > 0 {
+            vec![root]
+        } else {
+            children_of(root).to_vec()
+        };
+        while children.len() < 4 {
+            let largest = children
+                .iter()
+                .enumerate()
+                .filter(|&(_, &c)| binary[c].count == 0)
+                .max_by(|&(_, &a), &(_, &b)| {
+                    let area = |i: usize| binary[i].bbox.surface_area();
+                    area(a).total_cmp(&area(b))
+                });
+            let Some((k, &c)) = largest else {
+                break;
+            };
+            children.swap_remove(k);
+            children.extend(children_of(c));
+        }
+
+        let index = self.nodes.len();
+        self.nodes.push(WideNode::EMPTY);
+        for (lane, &c) in children.iter().enumerate() {
+            let node = &binary[c];
+            let (child, count) = if node.count > 0 {
+                (node.offset, node.count)
+            } else {
+                (self.collapse(binary, c), 0)
+            };
+
+            let wide = &mut self.nodes[index];
+            wide.set_lane_box(lane, &node.bbox);
+            wide.child[lane] = child;
+            wide.count[lane] = count;
+        }
+        index as u32
+    }
+
+    pub fn bounding_box(&self) -> &AABB {
+        &self.bbox
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !(0..4).any(|lane| self.nodes[0].is_used(lane))
+    }
+
+    pub fn children(&self) -> impl Iterator<Item = (AABB, u32)> + '_ {
+        // The box and primitive count of every child of every node, 0 for interior children.
+        self.nodes.iter().flat_map(|node| {
+            (0..4)
+                .filter(|&lane| node.is_used(lane))
+                .map(|lane| (node.lane_box(lane), node.count[lane]))
+        })
+    }
+
+    pub fn transform(&mut self, transform: &Transform) {
+        // Replaces the child boxes by the bounds of their transformed corners.
+        for node in &mut self.nodes {
+            for lane in 0..4 {
+                if node.is_used(lane) {
+                    let bbox = transform.bbox(&node.lane_box(lane));
+                    node.set_lane_box(lane, &bbox);
+                }
+            }
+        }
+        self.bbox = transform.bbox(&self.bbox);
+    }
+
+    pub fn refit(&mut self, bboxes: &[AABB]) {
+        // Recomputes the child boxes from the primitive boxes, given in storage order. Children
+        // are stored after their parent, so a backward pass updates them first. The bounds of
+        // each node are kept in f64 on the way, so that rounding doesn't add up along the tree.
+        let mut node_bounds = vec![AABB::empty(); self.nodes.len()];
+        for i in (0..self.nodes.len()).rev() {
+            let mut bounds = AABB::empty();
+            for lane in 0..4 {
+                let node = &self.nodes[i];
+                let (child, count) = (node.child[lane] as usize, node.count[lane] as usize);
+                let bbox = if count > 0 {
+                    bboxes[child..child + count]
+                        .iter()
+                        .fold(AABB::empty(), |bbox, b| AABB::from_aabbs(&bbox, b))
+                } else if child > 0 {
+                    node_bounds[child].clone()
+                } else {
+                    continue;
+                };
+                self.nodes[i].set_lane_box(lane, &bbox);
+                bounds = AABB::from_aabbs(&bounds, &bbox);
+            }
+            node_bounds[i] = bounds;
+        }
+        self.bbox = node_bounds.swap_remove(0);
+    }
+
+    pub fn write_cache(&self, out: &mut CacheWriter) {
+        out.aabb(&self.bbox);
+        out.len(self.nodes.len());
+        for node in &self.nodes {
+            node.bounds
+                .iter()
+                .flatten()
+                .for_each(|&x| out.f64(widen(x)));
+            node.child.iter().for_each(|&c| out.u32(c));
+            node.count.iter().for_each(|&c| out.u32(c));
+        }
+    }
+
+    pub fn read_cache(input: &mut CacheReader, primitive_count: usize) -> io::Result<Self> {
+        // Checks that the child indices and leaf ranges read stay within the tree and the
+        // primitives, since the traversal relies on them. Interior children must come after
+        // their parent, so the traversal ends. Unused lanes get empty boxes again.
+        const NODE_SIZE: usize = 24 * 8 + 8 * 4;
+        let bbox = input.aabb()?;
+        let len = input.len(NODE_SIZE)?;
+        let mut nodes = Vec::with_capacity(len);
+        for i in 0..len {
+            let mut node = WideNode::EMPTY;
+            for side in &mut node.bounds {
+                for x in side {
+                    *x = input.f64()? as Real;
+                }
+            }
+            for c in &mut node.child {
+                *c = input.u32()?;
+            }
+            for c in &mut node.count {
+                *c = input.u32()?;
+            }
+
+            for lane in 0..4 {
+                let (child, count) = (node.child[lane] as usize, node.count[lane] as usize);
+                let valid = match count {
+                    0 if child == 0 => {
+                        node.set_lane_box(lane, &AABB::empty());
+                        true
+                    }
+                    0 => i < child && child < len,
+                    _ => child
+                        .checked_add(count)
+                        .is_some_and(|end| end <= primitive_count),
+                };
+                if !valid {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "invalid BVH node in cache file",
+                    ));
+                }
+            }
+            nodes.push(node);
+        }
+
+        if nodes.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "empty BVH in cache file",
+            ));
+        }
+        Ok(Self { nodes, bbox })
+    }
+
+    pub fn hit(
+        &self,
+        r: &Ray,
+        ray_t: Interval,
+        hit_primitive: impl FnMut(usize, Interval) -> Option<f64>,
+    ) {
+        #[cfg(target_arch = "x86_64")]
+        if is_x86_feature_detected!("avx") {
+            // SAFETY: the CPU supports AVX.
+            unsafe { self.hit_avx(r, ray_t, hit_primitive) };
+            return;
+        }
+        self.traverse(r, ray_t, hit_primitive, child_hits);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[target_feature(enable = "avx")]
+    fn hit_avx(
+        &self,
+        r: &Ray,
+        ray_t: Interval,
+        hit_primitive: impl FnMut(usize, Interval) -> Option<f64>,
+    ) {
+        self.traverse(r, ray_t, hit_primitive, |node, ray| {
+            child_hits_avx(node, ray)
+        });
+    }
+
+    #[inline(always)]
+    fn traverse(
+        &self,
+        r: &Ray,
+        ray_t: Interval,
+        mut hit_primitive: impl FnMut(usize, Interval) -> Option<f64>,
+        child_hits: impl Fn(&WideNode, &RayLanes) -> (u32, [f64; 4]),
+    ) {
+        // Same contract as LinearBVH::hit. The children a ray hits are pushed farthest first,
+        // so the nearest is visited next, and entries are dropped once a closer hit was found.
+        let inv_dir = r.inv_direction();
+        let neg = [0, 1, 2].map(|axis| (inv_dir[axis] < 0.0) as usize);
+        let towards = |axis: usize, up: bool| {
+            // Rounding the origin up moves the slab distances down for rays going towards
+            // positive coordinates, and up for the others.
+            let x = r.origin()[axis];
+            if up { round_up(x) } else { round_down(x) }
+        };
+        let mut ray = RayLanes {
+            origin: [
+                [0, 1, 2].map(|axis| towards(axis, neg[axis] == 0)),
+                [0, 1, 2].map(|axis| towards(axis, neg[axis] == 1)),
+            ],
+            inv_dir: [0, 1, 2].map(|axis| inv_dir[axis] as Real),
+            neg,
+            t_min: round_down(ray_t.min),
+            t_max: round_up(ray_t.max),
+        };
+        let mut t_max = ray_t.max;
+
+        let mut stack = Vec::with_capacity(64);
+        stack.push(StackEntry {
+            child: 0,
+            count: 0,
+            t_near: ray_t.min,
+        });
+        while let Some(entry) = stack.pop() {
+            if entry.t_near >= t_max {
+                continue;
+            }
+
+            if entry.count > 0 {
+                let first = entry.child as usize;
+                for i in first..first + entry.count as usize {
+                    if let Some(t) = hit_primitive(i, Interval::from(ray_t.min, t_max)) {
+                        t_max = t;
+                        ray.t_max = round_up(t);
+                        if t_max <= ray_t.min {
+                            return;
+                        }
+                    }
+                }
+                continue;
+            }
+
+            let node = &self.nodes[entry.child as usize];
+            let (mask, t_near) = child_hits(node, &ray);
+            let mut hits = [entry; 4];
+            let mut n = 0;
+            for (lane, &t_near) in t_near.iter().enumerate() {
+                if mask & (1 << lane) != 0 {
+                    hits[n] = StackEntry {
+                        child: node.child[lane],
+                        count: node.count[lane],
+                        t_near,
+                    };
+                    n += 1;
+                }
+            }
+            hits[..n].sort_unstable_by(|a, b| b.t_near.total_cmp(&a.t_near));
+            stack.extend_from_slice(&hits[..n]);
+        }
+    }
+}
+
+fn child_hits(node: &WideNode, ray: &RayLanes) -> (u32, [f64; 4]) {
+    // Slab test of the four children. Returns the mask of the children hit within
+    // [t_min, t_max] and lower bounds of the distances at which the ray enters them.
+    let mut t_near = [-Real::INFINITY; 4];
+    let mut t_far = [Real::INFINITY; 4];
+    for axis in 0..3 {
+        let near = &node.bounds[2 * axis + ray.neg[axis]];
+        let far = &node.bounds[2 * axis + 1 - ray.neg[axis]];
+        for lane in 0..4 {
+            let near = (near[lane] - ray.origin[0][axis]) * ray.inv_dir[axis];
+            let far = (far[lane] - ray.origin[1][axis]) * ray.inv_dir[axis];
+            t_near[lane] = t_near[lane].max(near);
+            t_far[lane] = t_far[lane].min(far);
+        }
+    }
+
+    // Widening each distance by its error bound is monotonic, so it can be applied to the
+    // largest near and the smallest far distance only.
+    let (down, up) = (1.0 - SLAB_ERROR, 1.0 + SLAB_ERROR);
+    let mut mask = 0;
+    for lane in 0..4 {
+        let (near, far) = (t_near[lane], t_far[lane]);
+        t_near[lane] = (near * down).min(near * up).max(ray.t_min);
+        let far = (far * down).max(far * up).min(ray.t_max);
+        mask |= ((t_near[lane] < far) as u32) << lane;
+    }
+    (mask, t_near.map(widen))
+}
+
+#[cfg(all(target_arch = "x86_64", not(feature = "f32")))]
+#[target_feature(enable = "avx")]
+fn child_hits_avx(node: &WideNode, ray: &RayLanes) -> (u32, [f64; 4]) {
+    // child_hits, one axis of the four boxes at a time.
+    use std::arch::x86_64::*;
+
+    let mut t_near = _mm256_set1_pd(-f64::INFINITY);
+    let mut t_far = _mm256_set1_pd(f64::INFINITY);
+    for axis in 0..3 {
+        let inv_dir = _mm256_set1_pd(ray.inv_dir[axis]);
+        // SAFETY: the node is 32-byte aligned and a side is 32 bytes long.
+        let (near, far) = unsafe {
+            (
+                _mm256_load_pd(node.bounds[2 * axis + ray.neg[axis]].as_ptr()),
+                _mm256_load_pd(node.bounds[2 * axis + 1 - ray.neg[axis]].as_ptr()),
+            )
+        };
+        let near = _mm256_sub_pd(near, _mm256_set1_pd(ray.origin[0][axis]));
+        let far = _mm256_sub_pd(far, _mm256_set1_pd(ray.origin[1][axis]));
+        // With a NaN operand, max and min return the second one. The slab distances go first, so
+        // a NaN from 0 * inf keeps the running values, as f64::max and f64::min do.
+        t_near = _mm256_max_pd(_mm256_mul_pd(near, inv_dir), t_near);
+        t_far = _mm256_min_pd(_mm256_mul_pd(far, inv_dir), t_far);
+    }
+
+    let (down, up) = (
+        _mm256_set1_pd(1.0 - SLAB_ERROR),
+        _mm256_set1_pd(1.0 + SLAB_ERROR),
+    );
+    let t_near = _mm256_max_pd(
+        _mm256_min_pd(_mm256_mul_pd(t_near, down), _mm256_mul_pd(t_near, up)),
+        _mm256_set1_pd(ray.t_min),
+    );
+    let t_far = _mm256_min_pd(
+        _mm256_max_pd(_mm256_mul_pd(t_far, down), _mm256_mul_pd(t_far, up)),
+        _mm256_set1_pd(ray.t_max),
+    );
+
+    let mask = _mm256_movemask_pd(_mm256_cmp_pd::<_CMP_LT_OQ>(t_near, t_far)) as u32;
+    let mut out = [0.0; 4];
+    // SAFETY: out holds four f64.
+    unsafe { _mm256_storeu_pd(out.as_mut_ptr(), t_near) };
+    (mask, out)
+}
+
+#[cfg(all(target_arch = "x86_64", feature = "f32"))]
+#[target_feature(enable = "avx")]
+fn child_hits_avx(node: &WideNode, ray: &RayLanes) -> (u32, [f64; 4]) {
+    // child_hits, one axis of the four boxes at a time. The near planes of the four children
+    // fill the low half of a register and their far planes the high half, whose distances are
+    // negated so that a single max keeps both the largest near and the smallest far distance.
+    use std::arch::x86_64::*;
+
+    let mut t = _mm256_set1_ps(-f32::INFINITY);
+    for axis in 0..3 {
+        let (o_near, o_far) = (ray.origin[0][axis], ray.origin[1][axis]);
+        let inv_dir = ray.inv_dir[axis];
+        // SAFETY: the node is 32-byte aligned and a side is 16 bytes long.
+        let bounds = unsafe {
+            _mm256_insertf128_ps::<1>(
+                _mm256_castps128_ps256(_mm_load_ps(node.bounds[2 * axis + ray.neg[axis]].as_ptr())),
+                _mm_load_ps(node.bounds[2 * axis + 1 - ray.neg[axis]].as_ptr()),
+            )
+        };
+        let origin = _mm256_setr_ps(o_near, o_near, o_near, o_near, o_far, o_far, o_far, o_far);
+        let inv_dir = _mm256_setr_ps(
+            inv_dir, inv_dir, inv_dir, inv_dir, -inv_dir, -inv_dir, -inv_dir, -inv_dir,
+        );
+        // With a NaN operand, max returns the second one. The slab distances go first, so a NaN
+        // from 0 * inf keeps the running values, as f32::max does.
+        t = _mm256_max_ps(_mm256_mul_ps(_mm256_sub_ps(bounds, origin), inv_dir), t);
+    }
+
+    // Lowering the near distances and the negated far ones widens both ends of the interval.
+    let (down, up) = (
+        _mm256_set1_ps(1.0 - SLAB_ERROR),
+        _mm256_set1_ps(1.0 + SLAB_ERROR),
+    );
+    let limits = _mm256_setr_ps(
+        ray.t_min, ray.t_min, ray.t_min, ray.t_min, -ray.t_max, -ray.t_max, -ray.t_max, -ray.t_max,
+    );
+    let t = _mm256_max_ps(
+        _mm256_min_ps(_mm256_mul_ps(t, down), _mm256_mul_ps(t, up)),
+        limits,
+    );
+
+    let (t_near, neg_far) = (_mm256_castps256_ps128(t), _mm256_extractf128_ps::<1>(t));
+    let t_far = _mm_xor_ps(neg_far, _mm_set1_ps(-0.0));
+    let mask = _mm_movemask_ps(_mm_cmplt_ps(t_near, t_far)) as u32;
+    let mut out = [0.0; 4];
+    // SAFETY: out holds four f64.
+    unsafe { _mm256_storeu_pd(out.as_mut_ptr(), _mm256_cvtps_pd(t_near)) };
+    (mask, out)
+}